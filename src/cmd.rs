@@ -1,5 +1,10 @@
+use std::fs;
 use std::process::Command;
 
+use eyre::{ErrReport, Result};
+
+use crate::forgery::{cache, config::CacheConfig};
+
 pub fn init(_args: Vec<String>) {
     println!("Initializing new Forgery project from boilerplate...");
 
@@ -12,3 +17,45 @@ pub fn init(_args: Vec<String>) {
 
     init.wait().expect("forge init failed");
 }
+
+pub fn cache(args: Vec<String>, config: &CacheConfig) -> Result<(), ErrReport> {
+    let entries = cache::entries(&config.dir)?;
+    match args.get(2).map(String::as_str) {
+        Some("ls") => {
+            for entry in entries.iter() {
+                println!(
+                    "chain {} block {}\t{}",
+                    entry.chain_id,
+                    entry.block,
+                    format_size(entry.size)
+                );
+            }
+        }
+        Some("size") => {
+            let size = entries.iter().map(|entry| entry.size).sum();
+            println!("{} in {} blocks", format_size(size), entries.len());
+        }
+        Some("clean") => {
+            // An optional chain id limits cleaning to that chain
+            let chain_id = args.get(3).filter(|arg| !arg.starts_with("--"));
+            let mut removed = 0;
+            for entry in entries.iter() {
+                if chain_id.map_or(true, |chain_id| *chain_id == entry.chain_id) {
+                    fs::remove_file(&entry.path)?;
+                    removed += 1;
+                }
+            }
+            println!("Removed {} cached blocks", removed);
+        }
+        _ => eyre::bail!("Usage: forgery cache <ls|size|clean [chain id]>"),
+    }
+    Ok(())
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        size if size >= 1024 * 1024 => format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0)),
+        size if size >= 1024 => format!("{:.1} KiB", size as f64 / 1024.0),
+        size => format!("{} B", size),
+    }
+}
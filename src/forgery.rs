@@ -1,2 +1,24 @@
+pub mod access;
+pub mod auth;
+pub mod body;
+pub mod broadcast;
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod events;
+pub mod fetch;
+pub mod fork;
+pub mod genesis;
+pub mod health;
+pub mod persistence;
+pub mod prefetch;
 pub mod project;
+pub mod rate_limit;
+pub mod response_cache;
+pub mod routes;
+pub mod static_files;
+pub mod stream;
 pub mod types;
+pub mod watch;
+pub mod websocket;
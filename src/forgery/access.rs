@@ -0,0 +1,152 @@
+use eyre::{Context, ErrReport, Result};
+use hyper::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION},
+    HeaderMap, Request,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+
+use crate::forgery::{
+    config::{AccessConfig, JwtAlgorithm},
+    routes,
+};
+
+/// Prefix of the headers verified claims are forwarded to handlers in
+pub const CLAIM_HEADER_PREFIX: &str = "x-forgery-claim-";
+
+struct Rule {
+    config: AccessConfig,
+    jwt: Option<(DecodingKey, Validation)>,
+}
+
+/// Guards path prefixes with static API keys or JWTs, so unauthorized requests never reach
+/// the executor.
+pub struct Access {
+    rules: Vec<Rule>,
+}
+
+impl Access {
+    /// Loads the JWT keys of every rule. HS256 secrets default to `FORGERY_JWT_SECRET`.
+    pub fn new(configs: Vec<AccessConfig>) -> Result<Self, ErrReport> {
+        let rules = configs
+            .into_iter()
+            .map(|config| {
+                let jwt = match &config.jwt {
+                    Some(jwt) => {
+                        let (algorithm, key) = match jwt.algorithm {
+                            JwtAlgorithm::HS256 => {
+                                let secret = match &jwt.secret {
+                                    Some(secret) => secret.clone(),
+                                    None => std::env::var("FORGERY_JWT_SECRET").wrap_err(
+                                        "FORGERY_JWT_SECRET must be set to verify HS256 tokens",
+                                    )?,
+                                };
+                                (
+                                    Algorithm::HS256,
+                                    DecodingKey::from_secret(secret.as_bytes()),
+                                )
+                            }
+                            JwtAlgorithm::ES256 => {
+                                let path = jwt.public_key.as_ref().ok_or_else(|| {
+                                    eyre::eyre!("ES256 tokens require a public_key")
+                                })?;
+                                let pem = std::fs::read(path)
+                                    .wrap_err_with(|| format!("Failed to read {path}"))?;
+                                (Algorithm::ES256, DecodingKey::from_ec_pem(&pem)?)
+                            }
+                        };
+                        let mut validation = Validation::new(algorithm);
+                        if let Some(issuer) = &jwt.issuer {
+                            validation.set_issuer(&[issuer]);
+                        }
+                        match &jwt.audience {
+                            Some(audience) => validation.set_audience(&[audience]),
+                            None => validation.validate_aud = false,
+                        }
+                        Some((key, validation))
+                    }
+                    None => None,
+                };
+                Ok(Rule { config, jwt })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Access { rules })
+    }
+
+    /// Checks the credentials of a request against the most specific rule for its path,
+    /// and replaces any claim headers it came with by the verified claims.
+    pub fn authorize<B>(&self, req: &mut Request<B>) -> Result<(), ErrReport> {
+        strip_claims(req.headers_mut());
+        let path = req.uri().path();
+        let Some(rule) = self
+            .rules
+            .iter()
+            .filter(|rule| routes::matches_prefix(&rule.config.prefix, path))
+            .max_by_key(|rule| rule.config.prefix.len())
+        else {
+            return Ok(());
+        };
+
+        let headers = req.headers();
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let api_key = headers
+            .get(rule.config.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .or(bearer);
+
+        let claims = if let Some(name) = api_key.and_then(|key| api_key_name(&rule.config, key)) {
+            Map::from_iter([("sub".to_string(), Value::String(name.to_string()))])
+        } else if let (Some((key, validation)), Some(token)) = (&rule.jwt, bearer) {
+            jsonwebtoken::decode::<Map<String, Value>>(token, key, validation)
+                .wrap_err("Invalid token")?
+                .claims
+        } else {
+            eyre::bail!("Missing or unknown credentials");
+        };
+
+        let headers = req.headers_mut();
+        for (claim, value) in claims {
+            let value = match value {
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+            let name = HeaderName::try_from(format!("{CLAIM_HEADER_PREFIX}{claim}"));
+            if let (Ok(name), Ok(value)) = (name, HeaderValue::try_from(value)) {
+                headers.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compares every key, so the time taken doesn't tell how much of a key was right
+fn api_key_name<'a>(config: &'a AccessConfig, key: &str) -> Option<&'a str> {
+    config
+        .api_keys
+        .iter()
+        .fold(None, |found: Option<&'a String>, (name, expected)| {
+            let matches = constant_time_eq(expected.as_bytes(), key.as_bytes());
+            found.or(matches.then_some(name))
+        })
+        .map(String::as_str)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Clients must not be able to pass claims of their own
+fn strip_claims(headers: &mut HeaderMap) {
+    let spoofed: Vec<HeaderName> = headers
+        .keys()
+        .filter(|name| name.as_str().starts_with(CLAIM_HEADER_PREFIX))
+        .cloned()
+        .collect();
+    for name in spoofed {
+        headers.remove(name);
+    }
+}
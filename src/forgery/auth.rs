@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex as StdMutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alloy_primitives::{hex, keccak256, Address, B256, U256};
+use alloy_sol_macro::sol;
+use alloy_sol_types::{Eip712Domain, SolStruct};
+use ethers_core::types::{RecoveryMessage, Signature};
+use eyre::{ErrReport, Result};
+use foundry_common::types::ToAlloy;
+use http_body_util::BodyExt;
+use hyper::{body::Incoming, header::CONTENT_TYPE, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+
+use crate::forgery::{
+    body::{self, ResponseBody},
+    config::AuthConfig,
+    types::{SolHttpHeader, SolHttpRequest},
+};
+
+/// Paths under which Forgery issues nonces and signs clients in
+pub const AUTH_PATH: &str = "/_forgery/auth";
+const NONCE_PATH: &str = "/_forgery/auth/nonce";
+const SIWE_PATH: &str = "/_forgery/auth/siwe";
+
+/// Header carrying the session token returned by a Sign-In with Ethereum
+pub const SESSION_HEADER: &str = "x-forgery-session";
+/// Headers of a request signed by the sender itself
+pub const SIGNATURE_HEADER: &str = "x-forgery-signature";
+pub const SIGNATURE_TYPE_HEADER: &str = "x-forgery-signature-type";
+pub const NONCE_HEADER: &str = "x-forgery-nonce";
+pub const EXPIRES_HEADER: &str = "x-forgery-expires";
+/// Header handlers find the verified sender in, only ever set by Forgery
+pub const SENDER_HEADER: &str = "x-forgery-sender";
+
+sol! {
+/// Typed data signed with `eth_signTypedData_v4` for `eip712` signed requests
+struct ForgeryRequest {
+    string method;
+    string uri;
+    bytes32 body;
+    string nonce;
+    uint256 expires;
+}
+}
+
+#[derive(Default)]
+struct AuthState {
+    /// Nonces handed out for SIWE messages, with their expiry
+    issued: HashMap<String, u64>,
+    /// Nonces of signed requests, kept until the requests expire to prevent replays
+    used: HashMap<(Address, String), u64>,
+    /// Session tokens with the address they belong to and their expiry
+    sessions: HashMap<String, (Address, u64)>,
+}
+
+/// Verifies wallet signatures, either once through Sign-In with Ethereum, or on every
+/// request through EIP-191 or EIP-712 signed headers.
+pub struct Auth {
+    config: AuthConfig,
+    domain: Eip712Domain,
+    state: StdMutex<AuthState>,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Self {
+        let domain = Eip712Domain::new(
+            Some(config.name.clone().into()),
+            Some(config.version.clone().into()),
+            config.chain_id.map(U256::from),
+            None,
+            None,
+        );
+        Auth {
+            config,
+            domain,
+            state: Default::default(),
+        }
+    }
+
+    /// Serves the nonce and sign-in endpoints.
+    pub async fn serve(&self, req: Request<Incoming>) -> Response<ResponseBody> {
+        match (req.method(), req.uri().path()) {
+            (&Method::GET, NONCE_PATH) => json(StatusCode::OK, self.issue_nonce()),
+            (&Method::POST, SIWE_PATH) => {
+                let sign_in = match req.collect().await {
+                    Ok(content) => serde_json::from_slice::<SignIn>(&content.to_bytes())
+                        .map_err(ErrReport::from)
+                        .and_then(|sign_in| self.sign_in(sign_in)),
+                    Err(err) => Err(err.into()),
+                };
+                match sign_in {
+                    Ok(session) => json(StatusCode::OK, session),
+                    Err(err) => unauthorized(err),
+                }
+            }
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(body::full("Not found"))
+                .unwrap(),
+        }
+    }
+
+    /// Address a request was sent by, the zero address if it carries no credentials.
+    /// Requests with invalid credentials are rejected.
+    pub fn authenticate(&self, request: &SolHttpRequest) -> Result<Address, ErrReport> {
+        if let Some(token) = header(request, SESSION_HEADER) {
+            let state = self.state.lock().unwrap();
+            return match state.sessions.get(token) {
+                Some((address, expires)) if *expires > now() => Ok(*address),
+                _ => eyre::bail!("Session expired or unknown"),
+            };
+        }
+        if header(request, SIGNATURE_HEADER).is_some() {
+            return self.verify_request(request);
+        }
+        Ok(Address::ZERO)
+    }
+
+    fn issue_nonce(&self) -> serde_json::Value {
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let now = now();
+        let mut state = self.state.lock().unwrap();
+        state.issued.retain(|_, expires| *expires > now);
+        state
+            .issued
+            .insert(nonce.clone(), now + self.config.nonce_ttl);
+        serde_json::json!({ "nonce": nonce })
+    }
+
+    fn sign_in(&self, sign_in: SignIn) -> Result<serde_json::Value, ErrReport> {
+        let message = SiweMessage::parse(&sign_in.message)?;
+        let now = now();
+        if let Some(domain) = &self.config.domain {
+            if message.domain != *domain {
+                eyre::bail!("Message is for another domain");
+            }
+        }
+        if let Some(chain_id) = self.config.chain_id {
+            if message.chain_id != chain_id {
+                eyre::bail!("Message is for another chain");
+            }
+        }
+        if message
+            .not_before
+            .map_or(false, |not_before| not_before > now)
+        {
+            eyre::bail!("Message is not valid yet");
+        }
+        if message
+            .expiration
+            .map_or(false, |expiration| expiration <= now)
+        {
+            eyre::bail!("Message expired");
+        }
+        let signer = recover(&sign_in.signature, sign_in.message.as_str().into())?;
+        if signer != message.address {
+            eyre::bail!("Message is not signed by {}", message.address);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match state.issued.remove(&message.nonce) {
+            Some(expires) if expires > now => {}
+            _ => eyre::bail!("Nonce expired or unknown"),
+        }
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let expires = message
+            .expiration
+            .unwrap_or(u64::MAX)
+            .min(now + self.config.session_ttl);
+        state.sessions.retain(|_, (_, expires)| *expires > now);
+        state
+            .sessions
+            .insert(token.clone(), (message.address, expires));
+        Ok(serde_json::json!({
+            "address": message.address,
+            "session": token,
+            "expires": expires,
+        }))
+    }
+
+    fn verify_request(&self, request: &SolHttpRequest) -> Result<Address, ErrReport> {
+        let signature = header(request, SIGNATURE_HEADER).unwrap_or_default();
+        let nonce = header(request, NONCE_HEADER)
+            .ok_or_else(|| eyre::eyre!("Signed requests must have a {NONCE_HEADER} header"))?;
+        let expires: u64 = header(request, EXPIRES_HEADER)
+            .and_then(|expires| expires.parse().ok())
+            .ok_or_else(|| eyre::eyre!("Signed requests must have a {EXPIRES_HEADER} header"))?;
+        let now = now();
+        if expires <= now {
+            eyre::bail!("Signature expired");
+        }
+        if expires > now + self.config.max_signature_age {
+            eyre::bail!("Signature expires too far in the future");
+        }
+
+        let body = keccak256(&request.body);
+        let signer = match header(request, SIGNATURE_TYPE_HEADER).unwrap_or("eip191") {
+            "eip191" => {
+                let message = format!(
+                    "{} {}\nnonce: {}\nexpires: {}\nbody: {}",
+                    request.method, request.uri, nonce, expires, body
+                );
+                recover(signature, message.into())?
+            }
+            "eip712" => {
+                let typed = ForgeryRequest {
+                    method: request.method.clone(),
+                    uri: request.uri.clone(),
+                    body,
+                    nonce: nonce.to_string(),
+                    expires: U256::from(expires),
+                };
+                let hash: B256 = typed.eip712_signing_hash(&self.domain);
+                recover(signature, hash.0.into())?
+            }
+            other => eyre::bail!("Unsupported signature type: {}", other),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.used.retain(|_, expires| *expires > now);
+        if state
+            .used
+            .insert((signer, nonce.to_string()), expires)
+            .is_some()
+        {
+            eyre::bail!("Nonce already used");
+        }
+        Ok(signer)
+    }
+}
+
+#[derive(Deserialize)]
+struct SignIn {
+    message: String,
+    signature: String,
+}
+
+/// The fields of an EIP-4361 message Forgery checks
+struct SiweMessage {
+    domain: String,
+    address: Address,
+    chain_id: u64,
+    nonce: String,
+    expiration: Option<u64>,
+    not_before: Option<u64>,
+}
+
+impl SiweMessage {
+    fn parse(message: &str) -> Result<Self, ErrReport> {
+        let mut lines = message.lines();
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(" wants you to sign in with your Ethereum account:"))
+            .ok_or_else(|| eyre::eyre!("Not a Sign-In with Ethereum message"))?;
+        let address = lines
+            .next()
+            .and_then(|line| Address::from_str(line.trim()).ok())
+            .ok_or_else(|| eyre::eyre!("Message has no valid address"))?;
+
+        let fields: HashMap<&str, &str> = lines.filter_map(|line| line.split_once(": ")).collect();
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| eyre::eyre!("Message has no {name}"))
+        };
+        let timestamp = |name: &str| -> Result<Option<u64>, ErrReport> {
+            fields
+                .get(name)
+                .map(|value| {
+                    let time = chrono::DateTime::parse_from_rfc3339(value)?;
+                    Ok(time.timestamp().max(0) as u64)
+                })
+                .transpose()
+        };
+
+        Ok(SiweMessage {
+            domain: domain.to_string(),
+            address,
+            chain_id: field("Chain ID")?.parse()?,
+            nonce: field("Nonce")?.to_string(),
+            expiration: timestamp("Expiration Time")?,
+            not_before: timestamp("Not Before")?,
+        })
+    }
+}
+
+/// Whether a request carries a session or a signature, which `authenticate` verifies.
+pub fn has_credentials<B>(req: &Request<B>) -> bool {
+    [SESSION_HEADER, SIGNATURE_HEADER]
+        .iter()
+        .any(|name| req.headers().contains_key(*name))
+}
+
+fn recover(signature: &str, message: RecoveryMessage) -> Result<Address, ErrReport> {
+    let signature = Signature::from_str(signature.trim())
+        .map_err(|err| eyre::eyre!("Invalid signature: {}", err))?;
+    Ok(signature.recover(message)?.to_alloy())
+}
+
+fn header<'a>(request: &'a SolHttpRequest, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .iter()
+        .find(|header| header.key.eq_ignore_ascii_case(name))
+        .map(|header| header.value.as_str())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+fn json(status: StatusCode, value: serde_json::Value) -> Response<ResponseBody> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body::full(value.to_string()))
+        .unwrap()
+}
+
+/// Passes the verified sender to handlers in the sender header, replacing any the client
+/// sent. Requests without credentials don't get one.
+pub fn set_sender(request: &mut SolHttpRequest, sender: Address) {
+    request
+        .headers
+        .retain(|header| !header.key.eq_ignore_ascii_case(SENDER_HEADER));
+    if sender != Address::ZERO {
+        request.headers.push(SolHttpHeader {
+            key: SENDER_HEADER.to_string(),
+            value: sender.to_string(),
+        });
+    }
+}
+
+pub fn unauthorized(err: ErrReport) -> Response<ResponseBody> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .body(body::full(err.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use ethers_core::utils::hash_message;
+    use ethers_signers::{LocalWallet, Signer};
+
+    use super::*;
+
+    const DOMAIN: &str = "app.example.com";
+
+    fn auth() -> Auth {
+        Auth::new(AuthConfig {
+            domain: Some(DOMAIN.to_string()),
+            chain_id: Some(1),
+            ..Default::default()
+        })
+    }
+
+    fn wallet() -> LocalWallet {
+        LocalWallet::new(&mut rand::thread_rng())
+    }
+
+    fn sign(wallet: &LocalWallet, message: &str) -> String {
+        wallet.sign_hash(hash_message(message)).unwrap().to_string()
+    }
+
+    fn siwe_message(address: Address, domain: &str, nonce: &str, expires_in: i64) -> String {
+        format!(
+            "{domain} wants you to sign in with your Ethereum account:\n{address}\n\n\
+             Sign in to the app.\n\nURI: https://{domain}\nVersion: 1\nChain ID: 1\n\
+             Nonce: {nonce}\nIssued At: {}\nExpiration Time: {}",
+            Utc::now().to_rfc3339(),
+            (Utc::now() + Duration::seconds(expires_in)).to_rfc3339()
+        )
+    }
+
+    fn issued_nonce(auth: &Auth) -> String {
+        auth.issue_nonce()["nonce"].as_str().unwrap().to_string()
+    }
+
+    fn sign_in(auth: &Auth, wallet: &LocalWallet, message: String) -> Result<serde_json::Value> {
+        let signature = sign(wallet, &message);
+        auth.sign_in(SignIn { message, signature })
+    }
+
+    fn signed_request(wallet: &LocalWallet, nonce: &str, expires: u64) -> SolHttpRequest {
+        let body = b"{}".to_vec();
+        let message = format!(
+            "POST /api/orders\nnonce: {}\nexpires: {}\nbody: {}",
+            nonce,
+            expires,
+            keccak256(&body)
+        );
+        let headers = [
+            (SIGNATURE_HEADER, sign(wallet, &message)),
+            (NONCE_HEADER, nonce.to_string()),
+            (EXPIRES_HEADER, expires.to_string()),
+        ];
+        SolHttpRequest {
+            method: "POST".to_string(),
+            uri: "/api/orders".to_string(),
+            headers: headers
+                .into_iter()
+                .map(|(key, value)| SolHttpHeader {
+                    key: key.to_string(),
+                    value,
+                })
+                .collect(),
+            body,
+        }
+    }
+
+    #[test]
+    fn set_sender_replaces_the_header_sent_by_the_client() {
+        let address = wallet().address().to_alloy();
+        let mut request = signed_request(&wallet(), "n1", now() + 60);
+        request.headers.push(SolHttpHeader {
+            key: "X-Forgery-Sender".to_string(),
+            value: Address::repeat_byte(1).to_string(),
+        });
+
+        set_sender(&mut request, address);
+        let senders: Vec<&str> = request
+            .headers
+            .iter()
+            .filter(|header| header.key.eq_ignore_ascii_case(SENDER_HEADER))
+            .map(|header| header.value.as_str())
+            .collect();
+        assert_eq!(senders, [address.to_string()]);
+
+        set_sender(&mut request, Address::ZERO);
+        assert_eq!(header(&request, SENDER_HEADER), None);
+    }
+
+    #[test]
+    fn siwe_message_parse_reads_the_checked_fields() {
+        let address = wallet().address().to_alloy();
+        let message = SiweMessage::parse(&siwe_message(address, DOMAIN, "abc123", 60)).unwrap();
+        assert_eq!(message.domain, DOMAIN);
+        assert_eq!(message.address, address);
+        assert_eq!(message.chain_id, 1);
+        assert_eq!(message.nonce, "abc123");
+        assert!(message.expiration.unwrap() > now());
+        assert!(message.not_before.is_none());
+
+        assert!(SiweMessage::parse("Sign in please").is_err());
+    }
+
+    #[test]
+    fn recover_returns_the_signer() {
+        let wallet = wallet();
+        let signature = sign(&wallet, "hello");
+        let signer = recover(&signature, "hello".into()).unwrap();
+        assert_eq!(signer, wallet.address().to_alloy());
+        let other = recover(&signature, "goodbye".into()).unwrap();
+        assert_ne!(other, wallet.address().to_alloy());
+        assert!(recover("0x1234", "hello".into()).is_err());
+    }
+
+    #[test]
+    fn sign_in_accepts_a_valid_signature_once() {
+        let auth = auth();
+        let wallet = wallet();
+        let address = wallet.address().to_alloy();
+        let nonce = issued_nonce(&auth);
+        let message = siwe_message(address, DOMAIN, &nonce, 60);
+
+        let session = sign_in(&auth, &wallet, message.clone()).unwrap();
+        assert_eq!(session["address"], serde_json::json!(address));
+        let token = session["session"].as_str().unwrap().to_string();
+        let request = SolHttpRequest {
+            headers: vec![SolHttpHeader {
+                key: SESSION_HEADER.to_string(),
+                value: token,
+            }],
+            ..signed_request(&wallet, "unused", now() + 60)
+        };
+        assert_eq!(auth.authenticate(&request).unwrap(), address);
+
+        // Nonces are single use
+        assert!(sign_in(&auth, &wallet, message).is_err());
+    }
+
+    #[test]
+    fn sign_in_rejects_another_domain() {
+        let auth = auth();
+        let wallet = wallet();
+        let nonce = issued_nonce(&auth);
+        let message = siwe_message(wallet.address().to_alloy(), "evil.example.com", &nonce, 60);
+        assert!(sign_in(&auth, &wallet, message).is_err());
+    }
+
+    #[test]
+    fn sign_in_rejects_an_unknown_nonce() {
+        let auth = auth();
+        let wallet = wallet();
+        let message = siwe_message(wallet.address().to_alloy(), DOMAIN, "not-issued", 60);
+        assert!(sign_in(&auth, &wallet, message).is_err());
+    }
+
+    #[test]
+    fn sign_in_rejects_an_expired_message() {
+        let auth = auth();
+        let wallet = wallet();
+        let nonce = issued_nonce(&auth);
+        let message = siwe_message(wallet.address().to_alloy(), DOMAIN, &nonce, -60);
+        assert!(sign_in(&auth, &wallet, message).is_err());
+    }
+
+    #[test]
+    fn sign_in_rejects_another_signer() {
+        let auth = auth();
+        let nonce = issued_nonce(&auth);
+        let message = siwe_message(wallet().address().to_alloy(), DOMAIN, &nonce, 60);
+        assert!(sign_in(&auth, &wallet(), message).is_err());
+    }
+
+    #[test]
+    fn verify_request_accepts_a_valid_signature_and_rejects_replays() {
+        let auth = auth();
+        let wallet = wallet();
+        let request = signed_request(&wallet, "1", now() + 60);
+        assert_eq!(
+            auth.verify_request(&request).unwrap(),
+            wallet.address().to_alloy()
+        );
+        assert!(auth.verify_request(&request).is_err());
+    }
+
+    #[test]
+    fn verify_request_rejects_expired_and_tampered_requests() {
+        let auth = auth();
+        let wallet = wallet();
+        let expired = signed_request(&wallet, "1", now() - 1);
+        assert!(auth.verify_request(&expired).is_err());
+        let too_far = signed_request(&wallet, "2", now() + 3600);
+        assert!(auth.verify_request(&too_far).is_err());
+
+        // Another body recovers another address, which isn't the sender
+        let mut tampered = signed_request(&wallet, "3", now() + 60);
+        tampered.body = b"{\"amount\":1}".to_vec();
+        let signer = auth.verify_request(&tampered).unwrap();
+        assert_ne!(signer, wallet.address().to_alloy());
+    }
+}
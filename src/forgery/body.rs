@@ -0,0 +1,41 @@
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::body::{Body, Bytes as HyperBytes, Frame};
+use tokio::sync::mpsc;
+
+/// Body of every response Forgery sends, either complete or streamed.
+pub type ResponseBody = BoxBody<HyperBytes, Infallible>;
+
+pub fn full(content: impl Into<HyperBytes>) -> ResponseBody {
+    Full::new(content.into()).boxed()
+}
+
+/// Body streaming the chunks sent through a channel. The response ends once every sender
+/// is dropped, and senders fail once the client goes away.
+pub struct ChannelBody {
+    receiver: mpsc::Receiver<HyperBytes>,
+}
+
+impl ChannelBody {
+    pub fn new(buffer: usize) -> (mpsc::Sender<HyperBytes>, ResponseBody) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        (sender, ChannelBody { receiver }.boxed())
+    }
+}
+
+impl Body for ChannelBody {
+    type Data = HyperBytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+    }
+}
@@ -0,0 +1,123 @@
+use alloy_primitives::{Address, B256};
+use ethers_core::types::{BlockNumber, TransactionRequest, U256};
+use ethers_middleware::SignerMiddleware;
+use ethers_providers::{Http, Middleware, Provider};
+use ethers_signers::{LocalWallet, Signer};
+use eyre::{Context, ErrReport, Result};
+use foundry_cheatcodes::{BroadcastableTransaction, BroadcastableTransactions};
+use foundry_common::types::{ToAlloy, ToEthers};
+use tokio::sync::Mutex;
+
+use crate::forgery::config::BroadcastConfig;
+
+/// Signs the transactions handlers record between `vm.startBroadcast()` and
+/// `vm.stopBroadcast()`, and sends them to the chain.
+pub struct Broadcaster {
+    client: SignerMiddleware<Provider<Http>, LocalWallet>,
+    /// Nonce of the next transaction, held while sending so concurrent requests don't reuse
+    /// it. Read from the RPC when not known.
+    nonce: Mutex<Option<U256>>,
+}
+
+/// A broadcast that failed part way, with the hashes of the transactions already sent.
+pub struct BroadcastError {
+    pub hashes: Vec<B256>,
+    pub error: ErrReport,
+}
+
+impl Broadcaster {
+    /// Loads the signing key, either from the configured keystore, unlocked with
+    /// `FORGERY_KEYSTORE_PASSWORD`, or from `FORGERY_PRIVATE_KEY`.
+    pub async fn new(config: &BroadcastConfig, rpc_url: &str) -> Result<Self, ErrReport> {
+        let wallet = match &config.keystore {
+            Some(path) => {
+                let password = std::env::var("FORGERY_KEYSTORE_PASSWORD")
+                    .wrap_err("FORGERY_KEYSTORE_PASSWORD must be set to unlock the keystore")?;
+                LocalWallet::decrypt_keystore(path, password)
+                    .wrap_err_with(|| format!("Failed to decrypt keystore {path}"))?
+            }
+            None => std::env::var("FORGERY_PRIVATE_KEY")
+                .wrap_err("FORGERY_PRIVATE_KEY must be set to broadcast transactions")?
+                .parse::<LocalWallet>()
+                .wrap_err("Invalid FORGERY_PRIVATE_KEY")?,
+        };
+
+        let provider = Provider::<Http>::try_from(rpc_url)?;
+        let client = SignerMiddleware::new_with_provider_chain(provider, wallet)
+            .await
+            .wrap_err("Failed to get the chain id of the broadcast RPC")?;
+        Ok(Broadcaster {
+            client,
+            nonce: Mutex::new(None),
+        })
+    }
+
+    /// Account transactions are signed with, handlers pass it to `vm.startBroadcast()`.
+    pub fn address(&self) -> Address {
+        self.client.signer().address().to_alloy()
+    }
+
+    /// Sends the transactions in order, returning their hashes. Gas and nonces are taken
+    /// from the chain, since the simulated ones may not hold there. Broadcasts are sent one
+    /// at a time.
+    pub async fn send(
+        &self,
+        transactions: BroadcastableTransactions,
+    ) -> Result<Vec<B256>, BroadcastError> {
+        let mut nonce = self.nonce.lock().await;
+        let mut hashes = Vec::new();
+        for broadcastable in transactions {
+            match self.send_one(broadcastable, &mut nonce).await {
+                Ok(hash) => hashes.push(hash),
+                Err(error) => {
+                    // The transaction may or may not have gone out, ask the chain next time
+                    *nonce = None;
+                    return Err(BroadcastError { hashes, error });
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    async fn send_one(
+        &self,
+        broadcastable: BroadcastableTransaction,
+        nonce: &mut Option<U256>,
+    ) -> Result<B256, ErrReport> {
+        let tx = broadcastable.transaction;
+        let from = tx.from.unwrap_or_default();
+        if from != self.address() {
+            eyre::bail!(
+                "Cannot broadcast from {}, only {} can sign",
+                from,
+                self.address()
+            );
+        }
+
+        let mut request = TransactionRequest::new()
+            .from(from.to_ethers())
+            .value(tx.value.unwrap_or_default().to_ethers())
+            .data(tx.input.input().cloned().unwrap_or_default().0);
+        if let Some(to) = tx.to {
+            request = request.to(to.to_ethers());
+        }
+
+        let next = match *nonce {
+            Some(next) => next,
+            None => self
+                .client
+                .get_transaction_count(from.to_ethers(), Some(BlockNumber::Pending.into()))
+                .await
+                .wrap_err("Failed to get the nonce of the broadcast account")?,
+        };
+        request = request.nonce(next);
+
+        let pending = self
+            .client
+            .send_transaction(request, None)
+            .await
+            .wrap_err("Failed to send transaction")?;
+        *nonce = Some(next + 1);
+        Ok(pending.tx_hash().to_alloy())
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use alloy_primitives::{Address, Bytes as EvmBytes, U256};
+use eyre::{Context, ErrReport, Result};
+use forge::{
+    backend::Backend,
+    executors::Executor,
+    revm::primitives::{AccountInfo, Bytecode, State},
+};
+use foundry_evm_core::{
+    backend::DatabaseExt,
+    constants::{CHEATCODE_ADDRESS, HARDHAT_CONSOLE_ADDRESS},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::forgery::{config::CacheConfig, prefetch::PrefetchList};
+
+/// Chain state read by handlers at a given block, written to disk as JSON.
+#[derive(Default, Serialize, Deserialize)]
+pub struct BlockCache {
+    accounts: BTreeMap<Address, CachedAccount>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CachedAccount {
+    info: Option<CachedInfo>,
+    storage: BTreeMap<U256, U256>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedInfo {
+    balance: U256,
+    nonce: u64,
+    code: Option<EvmBytes>,
+}
+
+/// Parts of an account committed locally, whose values no longer come from the chain.
+#[derive(Default)]
+struct Written {
+    info: bool,
+    storage: HashSet<U256>,
+}
+
+/// Keeps the state read from the fork at the current block, so restarting at the same block
+/// doesn't have to fetch it from the RPC again.
+///
+/// Files are stored under `<dir>/<chain id>/<block>.json`.
+pub struct ForkCache {
+    config: CacheConfig,
+    chain_id: u64,
+    block: u64,
+    data: BlockCache,
+    dirty: bool,
+    /// Written since the fork moved to the current block, left out of the cache
+    written: HashMap<Address, Written>,
+}
+
+impl ForkCache {
+    pub fn new(config: CacheConfig, chain_id: u64, block: u64) -> Result<Self, ErrReport> {
+        let mut cache = ForkCache {
+            config,
+            chain_id,
+            block,
+            data: Default::default(),
+            dirty: false,
+            written: Default::default(),
+        };
+        cache.data = cache.load(block)?;
+        Ok(cache)
+    }
+
+    fn path(&self, block: u64) -> PathBuf {
+        Path::new(&self.config.dir)
+            .join(self.chain_id.to_string())
+            .join(format!("{block}.json"))
+    }
+
+    fn load(&self, block: u64) -> Result<BlockCache, ErrReport> {
+        let path = self.path(block);
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        serde_json::from_str(&content)
+            .wrap_err_with(|| format!("Failed to parse cache file {}", path.display()))
+    }
+
+    /// Records the state a call read from the fork, before it is committed. Index contracts
+    /// and other persistent accounts hold local state, so they are left out, and so is
+    /// anything earlier calls wrote to.
+    pub fn record(&mut self, backend: &Backend, state: &State) {
+        for (address, account) in state.iter() {
+            if *address == CHEATCODE_ADDRESS
+                || *address == HARDHAT_CONSOLE_ADDRESS
+                || backend.is_persistent(address)
+                || account.is_created()
+            {
+                continue;
+            }
+            let code = account
+                .info
+                .code
+                .as_ref()
+                .map(|code| code.original_bytes())
+                .filter(|code| !code.is_empty());
+            // Contracts on chain have a non-zero nonce, code without one was inserted by
+            // Forgery, such as fetch responses
+            if code.is_some() && account.info.nonce == 0 {
+                continue;
+            }
+
+            let written = self.written.entry(*address).or_default();
+            let cached = self.data.accounts.entry(*address).or_default();
+            // The balance and nonce of touched accounts may already differ from the chain
+            if account.is_touched() {
+                written.info = true;
+            } else if !written.info {
+                cached.info = Some(CachedInfo {
+                    balance: account.info.balance,
+                    nonce: account.info.nonce,
+                    code,
+                });
+            }
+            // Original values are those of the last commit, which is the chain only until
+            // the slot is first written
+            for (slot, value) in account.storage.iter() {
+                if written.storage.contains(slot) {
+                    continue;
+                }
+                cached.storage.insert(*slot, value.original_value());
+                if value.is_changed() {
+                    written.storage.insert(*slot);
+                }
+            }
+            self.dirty = true;
+        }
+    }
+
+    /// Adds the accounts and slots read at the current block to a prefetch list.
+    pub fn accessed(&self, list: &mut PrefetchList) {
+        for (address, account) in self.data.accounts.iter() {
+            list.entry(*address)
+                .or_default()
+                .extend(account.storage.keys().copied());
+        }
+    }
+
+    /// Loads the cached state into the active fork, so it is not requested from the RPC.
+    pub fn warm(&self, executor: &mut Executor) -> Result<(), ErrReport> {
+        for (address, account) in self.data.accounts.iter() {
+            if let Some(info) = &account.info {
+                let mut account_info = AccountInfo {
+                    balance: info.balance,
+                    nonce: info.nonce,
+                    ..Default::default()
+                };
+                if let Some(code) = &info.code {
+                    let code = Bytecode::new_raw(code.clone());
+                    account_info.code_hash = code.hash_slow();
+                    account_info.code = Some(code);
+                }
+                executor.backend.insert_account_info(*address, account_info);
+            }
+            for (slot, value) in account.storage.iter() {
+                executor
+                    .backend
+                    .insert_account_storage(*address, *slot, *value)?;
+            }
+        }
+        if !self.data.accounts.is_empty() {
+            println!(
+                "Warmed fork with {} cached accounts at block {}",
+                self.data.accounts.len(),
+                self.block
+            );
+        }
+        Ok(())
+    }
+
+    /// Flushes the current block and switches to another one, loading it if cached.
+    pub fn set_block(&mut self, block: u64) -> Result<(), ErrReport> {
+        if block == self.block {
+            return Ok(());
+        }
+        self.flush()?;
+        self.block = block;
+        self.data = self.load(block)?;
+        // Rolling drops local writes to accounts that aren't persistent
+        self.written.clear();
+        Ok(())
+    }
+
+    /// Writes the current block to disk if it changed, evicting old blocks when the cache
+    /// grows past its maximum size.
+    pub fn flush(&mut self) -> Result<(), ErrReport> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = self.path(self.block);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&self.data)?)?;
+        fs::rename(&tmp_path, &path)?;
+        self.dirty = false;
+
+        prune(&self.config.dir, self.config.max_size, &path)
+    }
+}
+
+/// A cached block, as listed by `forgery cache ls`.
+pub struct CacheEntry {
+    pub chain_id: String,
+    pub block: String,
+    pub size: u64,
+    pub path: PathBuf,
+    modified: SystemTime,
+}
+
+/// Every cached block, oldest first.
+pub fn entries(dir: &str) -> Result<Vec<CacheEntry>, ErrReport> {
+    let mut entries = Vec::new();
+    let Ok(chains) = fs::read_dir(dir) else {
+        return Ok(entries);
+    };
+    for chain in chains.flatten() {
+        if !chain.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(chain.path())?.flatten() {
+            let path = file.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let metadata = file.metadata()?;
+            entries.push(CacheEntry {
+                chain_id: chain.file_name().to_string_lossy().into_owned(),
+                block: path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                path,
+            });
+        }
+    }
+    entries.sort_by_key(|entry| entry.modified);
+    Ok(entries)
+}
+
+// Removes the least recently written blocks until the cache fits, keeping the current one
+fn prune(dir: &str, max_size: u64, current: &Path) -> Result<(), ErrReport> {
+    let entries = entries(dir)?;
+    let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+    for entry in entries {
+        if size <= max_size {
+            break;
+        }
+        if entry.path == current {
+            continue;
+        }
+        fs::remove_file(&entry.path)?;
+        size -= entry.size;
+    }
+    Ok(())
+}
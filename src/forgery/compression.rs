@@ -0,0 +1,167 @@
+use std::io::{Read, Write};
+
+use eyre::{ErrReport, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use http_body_util::BodyExt;
+use hyper::{
+    header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+    Response,
+};
+
+use crate::forgery::{
+    body::{self, ResponseBody},
+    config::CompressionConfig,
+    types::SolHttpRequest,
+};
+
+/// Encodings Forgery compresses with, in order of preference
+const ENCODINGS: [&str; 3] = ["br", "zstd", "gzip"];
+
+/// Compresses a complete response body with the preferred encoding the client accepts.
+/// Responses that are small, already encoded or of another content type are left as is.
+pub async fn compress(
+    config: &CompressionConfig,
+    accept_encoding: Option<&HeaderValue>,
+    response: Response<ResponseBody>,
+) -> Response<ResponseBody> {
+    if !config.enabled || response.headers().contains_key(CONTENT_ENCODING) {
+        return response;
+    }
+    let Some(encoding) = accept_encoding
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate)
+    else {
+        return response;
+    };
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !compressible(config, content_type) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let content = match body.collect().await {
+        Ok(content) => content.to_bytes(),
+        Err(never) => match never {},
+    };
+    if content.len() < config.min_size {
+        return Response::from_parts(parts, body::full(content));
+    }
+
+    let compressed = match encode(encoding, &content) {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            println!("Error compressing response: {}", err);
+            return Response::from_parts(parts, body::full(content));
+        }
+    };
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body::full(compressed))
+}
+
+/// Decompresses a gzip encoded request body, so handlers always get the plain body.
+pub fn decompress_request(
+    config: &CompressionConfig,
+    request: &mut SolHttpRequest,
+) -> Result<(), ErrReport> {
+    let Some(index) = request
+        .headers
+        .iter()
+        .position(|header| header.key.eq_ignore_ascii_case(CONTENT_ENCODING.as_str()))
+    else {
+        return Ok(());
+    };
+    if !request.headers[index]
+        .value
+        .trim()
+        .eq_ignore_ascii_case("gzip")
+    {
+        eyre::bail!(
+            "Unsupported request encoding: {}",
+            request.headers[index].value
+        );
+    }
+
+    let mut body = Vec::new();
+    GzDecoder::new(request.body.as_slice())
+        .take(config.max_request_size as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > config.max_request_size {
+        eyre::bail!("Decompressed request body is too large");
+    }
+
+    request.body = body;
+    request.headers.retain(|header| {
+        !header.key.eq_ignore_ascii_case(CONTENT_ENCODING.as_str())
+            && !header.key.eq_ignore_ascii_case(CONTENT_LENGTH.as_str())
+    });
+    Ok(())
+}
+
+// Picks the first supported encoding with a non-zero quality
+fn negotiate(accept_encoding: &str) -> Option<&'static str> {
+    let accepted: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let name = params.next().filter(|name| !name.is_empty())?;
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+            Some((name, quality))
+        })
+        .collect();
+
+    ENCODINGS.into_iter().find(|encoding| {
+        accepted
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(encoding))
+            .or_else(|| accepted.iter().find(|(name, _)| *name == "*"))
+            .map_or(false, |(_, quality)| *quality > 0.0)
+    })
+}
+
+fn compressible(config: &CompressionConfig, content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    config
+        .content_types
+        .iter()
+        .any(|allowed| match allowed.strip_suffix("/*") {
+            Some(kind) => essence
+                .split_once('/')
+                .map_or(false, |(essence_kind, _)| essence_kind == kind),
+            None => essence == *allowed,
+        })
+}
+
+fn encode(encoding: &str, content: &[u8]) -> Result<Vec<u8>, ErrReport> {
+    Ok(match encoding {
+        "br" => {
+            let mut compressed = Vec::new();
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+            encoder.write_all(content)?;
+            drop(encoder);
+            compressed
+        }
+        "zstd" => zstd::encode_all(content, 3)?,
+        _ => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(content)?;
+            encoder.finish()?
+        }
+    })
+}
@@ -0,0 +1,737 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use alloy_primitives::{Address, B256, U256};
+use eyre::{Context, ErrReport, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::forgery::types::SolConfigEntry;
+
+const CONFIG_PATH: &str = "./forgery.toml";
+const PUBLIC_ENV_PREFIX: &str = "FORGERY_PUBLIC_";
+/// Variables the compiler toolchain needs to run, which hold no secrets
+const SYSTEM_ENV: [&str; 10] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TMPDIR",
+    "TMP",
+    "TEMP",
+    "SYSTEMROOT",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+];
+
+/// Forgery runtime configuration, read from `forgery.toml` and overridden by CLI flags.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForgeryConfig {
+    /// Path of the source file containing the index contract
+    pub entry: String,
+    /// Name of the index contract, required if the entry file has more than one contract
+    pub contract: Option<String>,
+    /// Index contracts mounted under path prefixes or hostnames, replaces `entry` when set
+    pub routes: Vec<RouteConfig>,
+    /// How index contracts are deployed, unless overridden by a route
+    pub deploy: DeployConfig,
+    /// Environment variables exposed to index contracts, on top of `FORGERY_PUBLIC_*` ones
+    pub env: Vec<String>,
+    /// Outbound HTTP requests made by handlers
+    pub fetch: FetchConfig,
+    /// Snapshots of contract storage kept across restarts, disabled if not set
+    pub persistence: Option<PersistenceConfig>,
+    /// Run on an empty in-memory backend instead of forking `FORGERY_RPC`
+    pub offline: bool,
+    /// Genesis allocation file used to seed accounts and contracts before deployment
+    pub alloc: Option<String>,
+    /// Block to fork from and RPC endpoints to fork through
+    pub fork: ForkConfig,
+    /// Additional forks handlers can switch to with `vm.selectFork`
+    pub forks: BTreeMap<String, ForkConfig>,
+    /// On-disk cache of the chain state read through the fork
+    pub cache: CacheConfig,
+    /// Signing and sending the transactions handlers broadcast, disabled if not set
+    pub broadcast: Option<BroadcastConfig>,
+    /// Compression of response bodies, and decompression of request bodies
+    pub compression: CompressionConfig,
+    /// Cross-origin requests from browsers, disallowed if not set
+    pub cors: Option<CorsConfig>,
+    /// Directories served as static files, before requests reach the index contracts
+    #[serde(rename = "static")]
+    pub static_files: Vec<StaticConfig>,
+    /// Responses of index contracts reused within a block, disabled if not set
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Request and gas budgets of clients, per path prefix
+    #[serde(rename = "rate_limit")]
+    pub rate_limits: Vec<RateLimitConfig>,
+    /// Proxies trusted to name the client they forward for in `X-Forwarded-For`
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Verification of wallet signatures, disabled if not set
+    pub auth: Option<AuthConfig>,
+    /// API keys or JWTs required under path prefixes
+    pub access: Vec<AccessConfig>,
+    /// Health, readiness and info endpoints served by Forgery itself
+    pub health: HealthConfig,
+}
+
+/// An index contract served for requests matching a path prefix and, optionally, a host.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    pub host: Option<String>,
+    pub entry: String,
+    pub contract: Option<String>,
+    pub deploy: Option<DeployConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeployConfig {
+    /// Account deploying the contract and calling `start()`
+    pub sender: Address,
+    /// Balance given to the contract before its constructor runs
+    #[serde(deserialize_with = "deserialize_u256")]
+    pub balance: U256,
+    /// Value sent along with the deployment
+    #[serde(deserialize_with = "deserialize_u256")]
+    pub value: U256,
+    /// Constructor arguments, encoded according to the constructor's ABI
+    pub args: Vec<String>,
+    /// Deploy through the CREATE2 deployer with this salt instead of using CREATE
+    #[serde(deserialize_with = "deserialize_salt")]
+    pub salt: Option<B256>,
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        DeployConfig {
+            sender: Address::ZERO,
+            balance: U256::MAX,
+            value: U256::ZERO,
+            args: Vec::new(),
+            salt: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Hosts handlers are allowed to call, fetching is disabled when empty
+    pub hosts: Vec<String>,
+    /// Timeout of a single request, in milliseconds
+    pub timeout: u64,
+    /// Maximum size of a response body, in bytes
+    pub max_response_size: usize,
+    /// Maximum number of fetches a single incoming request can trigger
+    pub max_requests: usize,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            hosts: Vec::new(),
+            timeout: 5000,
+            max_response_size: 1024 * 1024,
+            max_requests: 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// File the snapshot is written to
+    pub path: String,
+    /// Seconds between snapshots
+    pub interval: u64,
+    /// Accounts persisted on top of the index contracts. These should only exist locally,
+    /// since restored accounts stop following the fork
+    pub accounts: Vec<Address>,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        PersistenceConfig {
+            path: "./.forgery/state.json".to_string(),
+            interval: 30,
+            accounts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BroadcastConfig {
+    /// Endpoint transactions are sent to, the first fork endpoint if not set
+    pub rpc: Option<String>,
+    /// Keystore holding the signing key, `FORGERY_PRIVATE_KEY` is used if not set
+    pub keystore: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticConfig {
+    pub dir: String,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Serve `index.html` for page loads of paths without a file, for client side routing
+    #[serde(default)]
+    pub spa: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, `*` allows any
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    /// Request headers allowed in preflights, any if empty
+    pub headers: Vec<String>,
+    /// Response headers exposed to scripts
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    /// Seconds browsers may cache a preflight for
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            origins: Vec::new(),
+            methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Bodies smaller than this many bytes are sent as is
+    pub min_size: usize,
+    /// Content types worth compressing, `type/*` matches every subtype
+    pub content_types: Vec<String>,
+    /// Maximum size of a decompressed request body, in bytes
+    pub max_request_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_size: 1024,
+            content_types: vec![
+                "text/*".to_string(),
+                "application/json".to_string(),
+                "application/javascript".to_string(),
+                "application/xml".to_string(),
+                "image/svg+xml".to_string(),
+            ],
+            max_request_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Directory holding one file per chain and block
+    pub dir: String,
+    /// Size in bytes above which the oldest blocks are evicted
+    pub max_size: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            dir: "./.forgery/cache".to_string(),
+            max_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    /// Prefix of the `health`, `ready` and `info` endpoints
+    pub prefix: String,
+    /// Blocks the fork may trail the chain by and still be ready, not checked if not set
+    pub max_lag: Option<u64>,
+    /// Seconds without an answer from the RPC after which the fork isn't ready
+    pub max_poll_age: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            enabled: true,
+            prefix: "/_forgery".to_string(),
+            max_lag: None,
+            max_poll_age: 30,
+        }
+    }
+}
+
+/// Credentials a path prefix requires, either one of its API keys or a valid JWT.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessConfig {
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// API keys by name, the name is forwarded to handlers as the `sub` claim
+    #[serde(default)]
+    pub api_keys: BTreeMap<String, String>,
+    /// Header API keys are read from, on top of `Authorization: Bearer`
+    #[serde(default = "default_api_key_header")]
+    pub header: String,
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    pub algorithm: JwtAlgorithm,
+    /// HS256 secret, `FORGERY_JWT_SECRET` if not set
+    pub secret: Option<String>,
+    /// Path of the PEM encoded ES256 public key
+    pub public_key: Option<String>,
+    /// Required `iss` claim, not checked if not set
+    pub issuer: Option<String>,
+    /// Required `aud` claim, not checked if not set
+    pub audience: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum JwtAlgorithm {
+    HS256,
+    ES256,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Domain Sign-In with Ethereum messages must be for, any if not set
+    pub domain: Option<String>,
+    /// Chain id of sign-in messages and of the EIP-712 domain, any if not set
+    pub chain_id: Option<u64>,
+    /// Name of the EIP-712 domain
+    pub name: String,
+    /// Version of the EIP-712 domain
+    pub version: String,
+    /// Seconds a sign-in nonce stays valid
+    pub nonce_ttl: u64,
+    /// Seconds a session lasts, unless the sign-in message expires earlier
+    pub session_ttl: u64,
+    /// Seconds ahead a signed request can expire at most
+    pub max_signature_age: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            domain: None,
+            chain_id: None,
+            name: "Forgery".to_string(),
+            version: "1".to_string(),
+            nonce_ttl: 300,
+            session_ttl: 24 * 60 * 60,
+            max_signature_age: 300,
+        }
+    }
+}
+
+/// Token buckets limiting the clients of a path prefix.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Verified claim identifying clients, such as `sub`, by IP if not set or missing
+    pub claim: Option<String>,
+    /// Requests per second a client makes on average
+    pub rate: f64,
+    /// Requests a client can make at once
+    pub burst: u32,
+    /// Gas a client's requests can consume, not limited if not set
+    pub gas: Option<GasQuotaConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GasQuotaConfig {
+    /// Gas per second a client consumes on average
+    pub rate: u64,
+    /// Gas a client can consume at once
+    pub burst: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseCacheConfig {
+    /// Request headers that responses vary on, on top of the method, host and URI
+    pub headers: Vec<String>,
+    /// Responses kept at most, new ones aren't cached once it's reached
+    pub max_entries: usize,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        ResponseCacheConfig {
+            headers: Vec::new(),
+            max_entries: 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForkConfig {
+    pub block: ForkBlock,
+    /// Fallback endpoints, used in order after the ones from `FORGERY_RPC`
+    pub endpoints: Vec<EndpointConfig>,
+    /// Rate limit applied to endpoints that don't set their own
+    pub compute_units_per_second: Option<u64>,
+    pub no_rpc_rate_limit: bool,
+    /// Also prefetch the slots read at the previous block, requires the cache
+    pub prefetch_accessed: bool,
+}
+
+impl Default for ForkConfig {
+    fn default() -> Self {
+        ForkConfig {
+            block: ForkBlock::Latest,
+            endpoints: Vec::new(),
+            compute_units_per_second: None,
+            no_rpc_rate_limit: true,
+            prefetch_accessed: false,
+        }
+    }
+}
+
+impl ForkConfig {
+    /// RPC endpoints in the order they are tried. `FORGERY_RPC` can hold several
+    /// comma-separated urls.
+    pub fn endpoints(&self, rpc_env: Option<String>) -> Vec<EndpointConfig> {
+        let mut endpoints: Vec<EndpointConfig> = rpc_env
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| EndpointConfig {
+                url: url.to_string(),
+                compute_units_per_second: None,
+            })
+            .collect();
+        endpoints.extend(self.endpoints.iter().cloned());
+        endpoints
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointConfig {
+    pub url: String,
+    pub compute_units_per_second: Option<u64>,
+}
+
+/// Block the fork starts at. Pinned numbers never move, tags follow the chain.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "RawForkBlock")]
+pub enum ForkBlock {
+    Number(u64),
+    Latest,
+    Finalized,
+    Safe,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawForkBlock {
+    Number(u64),
+    Tag(String),
+}
+
+impl TryFrom<RawForkBlock> for ForkBlock {
+    type Error = String;
+
+    fn try_from(value: RawForkBlock) -> Result<Self, Self::Error> {
+        match value {
+            RawForkBlock::Number(number) => Ok(ForkBlock::Number(number)),
+            RawForkBlock::Tag(tag) => tag.parse(),
+        }
+    }
+}
+
+impl FromStr for ForkBlock {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "latest" => Ok(ForkBlock::Latest),
+            "finalized" => Ok(ForkBlock::Finalized),
+            "safe" => Ok(ForkBlock::Safe),
+            number => number
+                .parse()
+                .map(ForkBlock::Number)
+                .map_err(|_| format!("Invalid fork block: {number}")),
+        }
+    }
+}
+
+// TOML integers only go up to i64, larger amounts are written as strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawU256 {
+    Integer(u64),
+    String(String),
+}
+
+impl TryFrom<RawU256> for U256 {
+    type Error = String;
+
+    fn try_from(value: RawU256) -> Result<Self, Self::Error> {
+        match value {
+            RawU256::Integer(value) => Ok(U256::from(value)),
+            RawU256::String(value) => U256::from_str(&value).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+fn deserialize_u256<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+    U256::try_from(RawU256::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+// Salts are either 32 bytes of hex, or a number
+fn deserialize_salt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<B256>, D::Error> {
+    let salt = match RawU256::deserialize(deserializer)? {
+        RawU256::String(value) if value.trim_start_matches("0x").len() == 64 => {
+            B256::from_str(&value).map_err(serde::de::Error::custom)?
+        }
+        value => U256::try_from(value)
+            .map_err(serde::de::Error::custom)?
+            .into(),
+    };
+    Ok(Some(salt))
+}
+
+fn default_prefix() -> String {
+    "/".to_string()
+}
+
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
+
+impl Default for ForgeryConfig {
+    fn default() -> Self {
+        ForgeryConfig {
+            entry: "./src/Index.sol".to_string(),
+            contract: None,
+            routes: Vec::new(),
+            deploy: Default::default(),
+            env: Vec::new(),
+            fetch: Default::default(),
+            persistence: None,
+            offline: false,
+            alloc: None,
+            fork: Default::default(),
+            forks: BTreeMap::new(),
+            cache: Default::default(),
+            broadcast: None,
+            compression: Default::default(),
+            cors: None,
+            static_files: Vec::new(),
+            response_cache: None,
+            rate_limits: Vec::new(),
+            trusted_proxies: Vec::new(),
+            auth: None,
+            access: Vec::new(),
+            health: Default::default(),
+        }
+    }
+}
+
+impl ForgeryConfig {
+    pub fn load(args: &[String]) -> Result<Self, ErrReport> {
+        let mut config: ForgeryConfig = match fs::read_to_string(CONFIG_PATH) {
+            Ok(content) => toml::from_str(&content).wrap_err("Failed to parse forgery.toml")?,
+            Err(_) => Default::default(),
+        };
+
+        let mut args = args.iter().skip(1).peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--entry" => config.entry = flag_value(arg, args.next())?,
+                "--tc" => config.contract = Some(flag_value(arg, args.next())?),
+                "--offline" => config.offline = true,
+                "--fork-block" => config.fork.block = parse_flag(arg, args.next())?,
+                "--cache-dir" => config.cache.dir = flag_value(arg, args.next())?,
+                "--no-cache" => config.cache.enabled = false,
+                "--static" => config.static_files.push(StaticConfig {
+                    dir: flag_value(arg, args.next())?,
+                    prefix: default_prefix(),
+                    spa: false,
+                }),
+                "--cors" => {
+                    let origin = flag_value(arg, args.next())?;
+                    let cors = config.cors.get_or_insert_with(Default::default);
+                    cors.origins.push(origin);
+                }
+                "--no-compression" => config.compression.enabled = false,
+                "--auth" => {
+                    config.auth.get_or_insert_with(Default::default);
+                }
+                "--response-cache" => {
+                    config.response_cache.get_or_insert_with(Default::default);
+                }
+                "--broadcast" => {
+                    config.broadcast.get_or_insert_with(Default::default);
+                }
+                "--alloc" => config.alloc = Some(flag_value(arg, args.next())?),
+                "--sender" => config.deploy.sender = parse_flag(arg, args.next())?,
+                "--balance" => config.deploy.balance = parse_flag(arg, args.next())?,
+                "--value" => config.deploy.value = parse_flag(arg, args.next())?,
+                "--salt" => config.deploy.salt = Some(parse_flag(arg, args.next())?),
+                "--constructor-args" => {
+                    config.deploy.args.clear();
+                    while let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                        config.deploy.args.push(value.clone());
+                    }
+                }
+                flag if flag.starts_with("--") => eyre::bail!("Unknown option: {}", flag),
+                _ => {}
+            }
+        }
+
+        if let Some(persistence) = &config.persistence {
+            if persistence.interval == 0 {
+                eyre::bail!("persistence.interval must be at least 1 second");
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Index contracts to deploy. Without explicit routes, the entry contract serves everything.
+    /// Routes always carry a deploy configuration, inherited from the top level if not set.
+    pub fn routes(&self) -> Vec<RouteConfig> {
+        if self.routes.is_empty() {
+            return vec![RouteConfig {
+                prefix: default_prefix(),
+                host: None,
+                entry: self.entry.clone(),
+                contract: self.contract.clone(),
+                deploy: Some(self.deploy.clone()),
+            }];
+        }
+        let mut routes = self.routes.clone();
+        for route in routes.iter_mut() {
+            route.deploy.get_or_insert_with(|| self.deploy.clone());
+        }
+        routes
+    }
+
+    /// Endpoints of every named fork. `FORGERY_RPC_<NAME>` is tried before the configured ones.
+    pub fn named_fork_endpoints(&self) -> BTreeMap<String, Vec<EndpointConfig>> {
+        self.forks
+            .iter()
+            .map(|(name, fork)| {
+                let rpc_env = std::env::var(format!("FORGERY_RPC_{}", name.to_uppercase())).ok();
+                (name.clone(), fork.endpoints(rpc_env))
+            })
+            .collect()
+    }
+
+    fn is_public_env(&self, key: &str) -> bool {
+        key.starts_with(PUBLIC_ENV_PREFIX) || self.env.iter().any(|name| name == key)
+    }
+
+    /// Environment variables passed to the `configure()` hook of index contracts.
+    pub fn public_env(&self) -> Vec<SolConfigEntry> {
+        std::env::vars()
+            .filter(|(key, _)| self.is_public_env(key))
+            .map(|(key, value)| SolConfigEntry { key, value })
+            .collect()
+    }
+
+    /// Removes every variable but the public ones from the process environment, so handlers
+    /// can't read secrets, Forgery's own or any other, through the `vm.env*` cheatcodes.
+    /// The few the compiler toolchain needs are kept as well.
+    pub fn scrub_env(&self) {
+        let keys: Vec<String> = std::env::vars_os()
+            .filter_map(|(key, _)| key.into_string().ok())
+            .filter(|key| {
+                !self.is_public_env(key)
+                    && !SYSTEM_ENV
+                        .iter()
+                        .any(|system| system.eq_ignore_ascii_case(key))
+            })
+            .collect();
+        for key in keys {
+            std::env::remove_var(key);
+        }
+    }
+}
+
+fn flag_value(flag: &str, value: Option<&String>) -> Result<String, ErrReport> {
+    value
+        .cloned()
+        .ok_or_else(|| eyre::eyre!("Missing value for {}", flag))
+}
+
+fn parse_flag<T>(flag: &str, value: Option<&String>) -> Result<T, ErrReport>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = flag_value(flag, value)?;
+    value
+        .parse()
+        .map_err(|err| eyre::eyre!("Invalid value for {}: {}", flag, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrub_env_removes_secrets_from_the_process_environment() {
+        std::env::set_var("FORGERY_SCRUB_TEST_SECRET", "secret");
+        std::env::set_var("FORGERY_PUBLIC_SCRUB_TEST", "public");
+        std::env::set_var("FORGERY_SCRUB_TEST_LISTED", "listed");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY_SCRUB_TEST", "secret");
+        let config = ForgeryConfig {
+            env: vec!["FORGERY_SCRUB_TEST_LISTED".to_string()],
+            ..Default::default()
+        };
+
+        config.scrub_env();
+
+        assert!(std::env::var("FORGERY_SCRUB_TEST_SECRET").is_err());
+        assert!(std::env::var("AWS_SECRET_ACCESS_KEY_SCRUB_TEST").is_err());
+        assert!(std::env::var("PATH").is_ok());
+        assert_eq!(
+            std::env::var("FORGERY_PUBLIC_SCRUB_TEST").unwrap(),
+            "public"
+        );
+        assert_eq!(
+            std::env::var("FORGERY_SCRUB_TEST_LISTED").unwrap(),
+            "listed"
+        );
+    }
+}
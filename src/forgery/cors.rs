@@ -0,0 +1,117 @@
+use hyper::{
+    header::{
+        HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN, VARY,
+    },
+    HeaderMap, Method, Request, Response, StatusCode,
+};
+
+use crate::forgery::{
+    body::{self, ResponseBody},
+    config::CorsConfig,
+};
+
+/// Answers CORS preflights and adds CORS headers to responses, so index contracts never
+/// have to deal with them.
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Cors { config }
+    }
+
+    /// Response to a preflight request, `None` for any other request. Preflights from
+    /// origins that aren't allowed get no CORS headers, which makes the browser block the
+    /// actual request.
+    pub fn preflight<B>(&self, req: &Request<B>) -> Option<Response<ResponseBody>> {
+        if req.method() != Method::OPTIONS {
+            return None;
+        }
+        let requested_method = req.headers().get(ACCESS_CONTROL_REQUEST_METHOD)?;
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(body::full(""))
+            .unwrap();
+
+        let origin = req.headers().get(ORIGIN);
+        let method_allowed = requested_method.to_str().map_or(false, |method| {
+            self.config
+                .methods
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(method))
+        });
+        if !method_allowed || !self.set_origin(origin, response.headers_mut()) {
+            return Some(response);
+        }
+
+        let headers = response.headers_mut();
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            header_list(&self.config.methods),
+        );
+        // Without a configured list, whatever the client asks for is allowed
+        let allowed_headers = if self.config.headers.is_empty() {
+            req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+        } else {
+            Some(header_list(&self.config.headers))
+        };
+        if let Some(allowed_headers) = allowed_headers {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        }
+        if let Some(max_age) = self.config.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+        Some(response)
+    }
+
+    /// Adds the CORS headers to the response of an actual request.
+    pub fn apply(&self, origin: Option<&HeaderValue>, response: &mut Response<ResponseBody>) {
+        let headers = response.headers_mut();
+        if self.set_origin(origin, headers) && !self.config.expose_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                header_list(&self.config.expose_headers),
+            );
+        }
+    }
+
+    // Returns whether the origin is allowed
+    fn set_origin(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) -> bool {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        let Some(origin) = origin else {
+            return false;
+        };
+        let any = self.config.origins.iter().any(|allowed| allowed == "*");
+        let listed = origin.to_str().map_or(false, |origin| {
+            self.config
+                .origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        });
+        if !any && !listed {
+            return false;
+        }
+
+        // Browsers reject the wildcard on requests with credentials, so the origin is echoed
+        if any && !self.config.credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        }
+        if self.config.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        true
+    }
+}
+
+fn header_list(values: &[String]) -> HeaderValue {
+    HeaderValue::from_str(&values.join(", ")).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
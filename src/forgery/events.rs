@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use alloy_primitives::{Address, B256};
+use forge::revm::primitives::Log;
+use foundry_evm_core::constants::HARDHAT_CONSOLE_ADDRESS;
+use hyper::{
+    body::Bytes as HyperBytes,
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Method, Request, Response, StatusCode,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::forgery::body::{self, ChannelBody, ResponseBody};
+
+/// Path clients subscribe to events on, under the prefix of the route emitting them
+pub const EVENTS_PATH: &str = "/_events";
+
+const CAPACITY: usize = 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Forwards the events emitted by index contracts to clients subscribed over Server-Sent
+/// Events. Every log is tagged with the index contract whose call emitted it, and clients
+/// only receive the logs of the route they subscribed on.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<(Address, Log)>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        EventBus { sender }
+    }
+}
+
+impl EventBus {
+    /// Publishes the logs of a successful call to an index contract. Console logs are
+    /// printed, not published.
+    pub fn publish(&self, route: Address, logs: &[Log]) {
+        for log in logs {
+            if log.address != HARDHAT_CONSOLE_ADDRESS {
+                // Sending only fails when nobody is subscribed
+                let _ = self.sender.send((route, log.clone()));
+            }
+        }
+    }
+
+    /// Opens a stream of the events of an index contract. Clients can filter events by their
+    /// first topic, the event selector, with one or more `topic` query parameters.
+    pub fn subscribe<B>(&self, route: Address, req: &Request<B>) -> Response<ResponseBody> {
+        let topics = match topics(req) {
+            Ok(topics) => topics,
+            Err(err) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(body::full(err))
+                    .unwrap()
+            }
+        };
+
+        let mut receiver = self.sender.subscribe();
+        let (sender, body) = ChannelBody::new(16);
+        tokio::task::spawn(async move {
+            let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+            loop {
+                let chunk = tokio::select! {
+                    log = receiver.recv() => match log {
+                        Ok((source, log)) if source == route && matches_topics(&log, &topics) => {
+                            format_event(&log)
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = keep_alive.tick() => ":\n\n".to_string(),
+                };
+                // The client went away
+                if sender.send(HyperBytes::from(chunk)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Response::builder()
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap()
+    }
+}
+
+/// Whether the request subscribes to the events of the route with the given prefix.
+pub fn is_subscribe<B>(req: &Request<B>, prefix: &str) -> bool {
+    req.method() == Method::GET
+        && req
+            .uri()
+            .path()
+            .strip_prefix(prefix.trim_end_matches('/'))
+            .map_or(false, |rest| rest == EVENTS_PATH)
+}
+
+fn topics<B>(req: &Request<B>) -> Result<Vec<B256>, String> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|param| param.strip_prefix("topic="))
+        .map(|topic| topic.parse().map_err(|_| format!("Invalid topic: {topic}")))
+        .collect()
+}
+
+fn matches_topics(log: &Log, topics: &[B256]) -> bool {
+    topics.is_empty()
+        || log
+            .topics
+            .first()
+            .map_or(false, |topic| topics.contains(topic))
+}
+
+fn format_event(log: &Log) -> String {
+    let event = serde_json::json!({
+        "address": log.address,
+        "topics": log.topics,
+        "data": log.data,
+    });
+    format!("event: log\ndata: {}\n\n", event)
+}
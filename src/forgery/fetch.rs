@@ -0,0 +1,183 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy_primitives::{keccak256, Address, Bytes as EvmBytes, U256};
+use alloy_sol_types::{SolError, SolValue};
+use eyre::{ContextCompat, ErrReport, Result};
+use forge::{
+    executors::{Executor, RawCallResult},
+    revm::primitives::{AccountInfo, Bytecode},
+};
+use http_body_util::{Full, Limited};
+use hyper::{body::Bytes as HyperBytes, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::forgery::{
+    config::FetchConfig,
+    fork::ForkStatus,
+    types::{ForgeryFetch, SolHttpRequest, SolHttpResponse},
+};
+
+/// Performs outbound HTTP requests on behalf of handlers.
+///
+/// Handlers request a fetch by reverting with `ForgeryFetch(request)`. Forgery then performs
+/// the request, stores the ABI encoded `SolHttpResponse` as the code of an address derived from
+/// the request, and replays the call. On replay the handler finds the response there and
+/// decodes it instead of reverting.
+pub struct Fetcher {
+    config: FetchConfig,
+    client: Client<HttpsConnector<HttpConnector>, Full<HyperBytes>>,
+    /// Fork requests are served on, told when the executor fails to read from it
+    fork: Option<Arc<ForkStatus>>,
+}
+
+impl Fetcher {
+    pub fn new(config: FetchConfig) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Fetcher {
+            config,
+            client,
+            fork: None,
+        }
+    }
+
+    /// Reports execution errors to the fork, so it fails over to another endpoint.
+    pub fn with_fork(mut self, fork: Option<Arc<ForkStatus>>) -> Self {
+        self.fork = fork;
+        self
+    }
+
+    /// Executes a call, serving every fetch it requests until it completes. The executor is
+    /// released while fetching, so a slow upstream doesn't hold up other requests, and the
+    /// guard held for the final call is handed back. `fetches` counts the fetches of the whole
+    /// incoming request, across the calls made for it.
+    pub async fn call<'a>(
+        &self,
+        executor_mutex: &'a Mutex<Executor>,
+        mut executor: MutexGuard<'a, Executor>,
+        from: Address,
+        to: Address,
+        calldata: EvmBytes,
+        fetches: &mut usize,
+    ) -> (MutexGuard<'a, Executor>, Result<RawCallResult, ErrReport>) {
+        let mut fetched: Vec<(Address, AccountInfo)> = Vec::new();
+        let result = loop {
+            // The fork may have rolled while fetching, which drops the responses
+            for (address, info) in fetched.iter() {
+                executor.backend.insert_account_info(*address, info.clone());
+            }
+            let res = match executor.call_raw(from, to, calldata.clone(), U256::ZERO) {
+                Ok(res) => res,
+                // Reverts are results, errors come from the backend failing to load state
+                Err(err) => {
+                    if let Some(fork) = &self.fork {
+                        fork.report_failure();
+                    }
+                    break Err(err);
+                }
+            };
+
+            let request = match fetch_request(&res) {
+                Some(request) => request,
+                None => break Ok(res),
+            };
+
+            if *fetches >= self.config.max_requests {
+                break Err(eyre::eyre!(
+                    "Request exceeded the limit of {} fetches",
+                    self.config.max_requests
+                ));
+            }
+
+            *fetches += 1;
+            let address = response_address(&request);
+            drop(executor);
+            let response = self.fetch(request).await;
+            executor = executor_mutex.lock().await;
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => break Err(err),
+            };
+
+            let code = Bytecode::new_raw(response.abi_encode().into());
+            let info = AccountInfo {
+                code_hash: code.hash_slow(),
+                code: Some(code),
+                ..Default::default()
+            };
+            fetched.push((address, info));
+        };
+
+        // Responses are only valid for the request that fetched them
+        for (address, _) in fetched {
+            executor
+                .backend
+                .insert_account_info(address, AccountInfo::default());
+        }
+
+        (executor, result)
+    }
+
+    async fn fetch(&self, request: SolHttpRequest) -> Result<SolHttpResponse, ErrReport> {
+        let uri: Uri = request.uri.parse()?;
+        let host = uri.host().wrap_err("Fetch URI has no host")?;
+        if !self
+            .config
+            .hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            eyre::bail!("Fetching from {} is not allowed", host);
+        }
+
+        let outgoing = request.into_outgoing()?;
+        let timeout = Duration::from_millis(self.config.timeout);
+        let response = tokio::time::timeout(timeout, async {
+            let response = self.client.request(outgoing).await?;
+            let response = response.map(|body| Limited::new(body, self.config.max_response_size));
+            SolHttpResponse::from_incoming(response)
+                .await
+                .map_err(|err| eyre::eyre!(err))
+        })
+        .await;
+
+        // Network failures are reported to the handler, which decides how to deal with them
+        Ok(match response {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => error_response(502, err),
+            Err(err) => error_response(504, err),
+        })
+    }
+}
+
+fn fetch_request(res: &RawCallResult) -> Option<SolHttpRequest> {
+    if !res.reverted {
+        return None;
+    }
+    ForgeryFetch::abi_decode(&res.result, true)
+        .ok()
+        .map(|error| error.request)
+}
+
+/// Address holding the response to a request, `address(uint160(uint256(keccak256(abi.encode(request)))))`
+fn response_address(request: &SolHttpRequest) -> Address {
+    Address::from_word(keccak256(request.abi_encode()))
+}
+
+fn error_response(status: u16, err: impl std::fmt::Display) -> SolHttpResponse {
+    SolHttpResponse {
+        status,
+        headers: Vec::new(),
+        body: err.to_string().into_bytes(),
+    }
+}
@@ -0,0 +1,465 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use alloy_primitives::{Address, U256};
+use alloy_providers::provider::{Provider, TempProvider};
+use alloy_rpc_types::BlockNumberOrTag;
+use eyre::{ErrReport, Result};
+use forge::{
+    executors::Executor,
+    fork::CreateFork,
+    opts::EvmOpts,
+    revm::{
+        precompile::{Precompiles, SpecId as PrecompileSpec},
+        primitives::{Env as RevmEnv, SpecId},
+        JournaledState,
+    },
+};
+use foundry_evm_core::backend::DatabaseExt;
+use tokio::sync::Mutex;
+
+use crate::forgery::{
+    cache::ForkCache,
+    config::{CacheConfig, EndpointConfig, ForkBlock, ForkConfig},
+    events::EventBus,
+    prefetch::{self, PrefetchList, Prefetched},
+    project::{self, Caches},
+    response_cache::ResponseCache,
+    routes::RouteTable,
+    types::onBlockCall,
+};
+
+const CACHE_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where a fork stands, readable without the executor lock.
+#[derive(Default)]
+pub struct ForkStatus {
+    block: AtomicU64,
+    /// Latest block of the chain, 0 until the RPC is polled
+    head: AtomicU64,
+    /// Unix time of the last successful poll of the RPC, 0 if never polled
+    polled: AtomicU64,
+    /// Set when a request failed reading from the fork, until the follower rotates
+    failed: AtomicBool,
+}
+
+impl ForkStatus {
+    pub fn block(&self) -> u64 {
+        self.block.load(Ordering::Relaxed)
+    }
+
+    pub fn head(&self) -> Option<u64> {
+        Some(self.head.load(Ordering::Relaxed)).filter(|head| *head > 0)
+    }
+
+    /// Blocks the fork is behind the chain, unknown until the RPC is polled.
+    pub fn lag(&self) -> Option<u64> {
+        self.head().map(|head| head.saturating_sub(self.block()))
+    }
+
+    /// Seconds since the RPC last answered, `None` if it never was polled.
+    pub fn since_poll(&self) -> Option<u64> {
+        let polled = self.polled.load(Ordering::Relaxed);
+        (polled > 0).then(|| unix_time().saturating_sub(polled))
+    }
+
+    /// Reports that the executor failed reading from a fork. The error may come from a named
+    /// fork, so the endpoint is checked before moving to the next one.
+    pub fn report_failure(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Keeps the fork at the configured block, rotating through the RPC endpoints when the
+/// current one fails.
+pub struct ForkFollower {
+    config: ForkConfig,
+    /// Fork to follow, the active one if not set
+    fork_id: Option<U256>,
+    endpoints: Vec<EndpointConfig>,
+    current: usize,
+    /// Fork created for each endpoint, reused when rotating back to it
+    forks: Vec<Option<U256>>,
+    block_number: U256,
+    opts: EvmOpts,
+    env: RevmEnv,
+    /// State read from the fork, kept in sync with the block it follows
+    cache: Option<Arc<StdMutex<ForkCache>>>,
+    /// Index contracts served on this fork, which get the `prefetch()` and `onBlock()` hooks
+    route_table: Option<Arc<RwLock<RouteTable>>>,
+    events: EventBus,
+    status: Arc<ForkStatus>,
+    /// Responses computed at the previous block, dropped when the fork rolls
+    response_cache: Option<Arc<ResponseCache>>,
+}
+
+impl ForkFollower {
+    /// Picks the first endpoint that responds, along with the block to fork from.
+    pub async fn connect(
+        config: ForkConfig,
+        endpoints: Vec<EndpointConfig>,
+        opts: EvmOpts,
+        env: RevmEnv,
+    ) -> Result<Self, ErrReport> {
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            match block_number(&endpoint.url, &config.block).await {
+                Ok(block_number) => {
+                    let status = ForkStatus::default();
+                    status
+                        .block
+                        .store(block_number.to::<u64>(), Ordering::Relaxed);
+                    return Ok(ForkFollower {
+                        config,
+                        fork_id: None,
+                        forks: vec![None; endpoints.len()],
+                        endpoints,
+                        current: index,
+                        block_number,
+                        opts,
+                        env,
+                        cache: None,
+                        route_table: None,
+                        events: Default::default(),
+                        status: Arc::new(status),
+                        response_cache: None,
+                    });
+                }
+                Err(err) => println!("RPC endpoint #{} is unavailable: {}", index, err),
+            }
+        }
+        eyre::bail!("None of the RPC endpoints are available")
+    }
+
+    /// Opens the on-disk cache for the chain and block of the fork.
+    pub async fn open_cache(
+        &mut self,
+        config: CacheConfig,
+    ) -> Result<Arc<StdMutex<ForkCache>>, ErrReport> {
+        let chain_id = chain_id(&self.endpoints[self.current].url).await?;
+        let cache = ForkCache::new(config, chain_id, self.block_number.to::<u64>())?;
+        let cache = Arc::new(StdMutex::new(cache));
+        self.cache = Some(cache.clone());
+        Ok(cache)
+    }
+
+    /// Runs the block hooks of the index contracts, publishing their events to the bus.
+    pub fn with_routes(mut self, route_table: Arc<RwLock<RouteTable>>, events: EventBus) -> Self {
+        self.route_table = Some(route_table);
+        self.events = events;
+        self
+    }
+
+    /// Status of the fork, kept up to date while following.
+    pub fn status(&self) -> Arc<ForkStatus> {
+        self.status.clone()
+    }
+
+    /// Clears the response cache whenever the fork moves to another block.
+    pub fn with_response_cache(mut self, response_cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(response_cache);
+        self
+    }
+
+    /// Options to create the executor's fork with.
+    pub fn evm_opts(&self) -> EvmOpts {
+        self.endpoint_opts(self.current, self.block_number)
+    }
+
+    /// Creates a separate fork in the executor, returning its id.
+    pub fn create_fork(&mut self, executor: &mut Executor) -> Result<U256, ErrReport> {
+        let fork = CreateFork {
+            url: self.endpoints[self.current].url.clone(),
+            enable_caching: false,
+            env: self.env.clone(),
+            evm_opts: self.evm_opts(),
+        };
+        let fork_id = executor.backend.create_fork(fork)?;
+        self.fork_id = Some(fork_id);
+        Ok(fork_id)
+    }
+
+    fn endpoint_opts(&self, index: usize, block_number: U256) -> EvmOpts {
+        let endpoint = &self.endpoints[index];
+        let compute_units_per_second = endpoint
+            .compute_units_per_second
+            .or(self.config.compute_units_per_second);
+        EvmOpts {
+            fork_url: Some(endpoint.url.clone()),
+            fork_block_number: Some(block_number.to::<u64>()),
+            compute_units_per_second,
+            no_rpc_rate_limit: compute_units_per_second.is_none() && self.config.no_rpc_rate_limit,
+            ..self.opts.clone()
+        }
+    }
+
+    /// Follows the configured block. Slots declared by the index contracts are prefetched at
+    /// startup and after every block roll, after which their `onBlock()` hook runs.
+    pub async fn follow(mut self, executor_mutex: Arc<Mutex<Executor>>) {
+        if let Some(prefetched) = self.prefetch(self.block_number).await {
+            let mut executor = executor_mutex.lock().await;
+            if let Err(e) = prefetched.apply(&mut executor) {
+                println!("Error loading prefetched state: {}", e);
+            }
+        }
+
+        let mut last_flush = Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if last_flush.elapsed() >= CACHE_FLUSH_INTERVAL {
+                self.flush_cache();
+                last_flush = Instant::now();
+            }
+
+            // Only rotate if this endpoint is the one failing, and not some named fork's
+            if self.status.failed.swap(false, Ordering::Relaxed)
+                && block_number(&self.endpoints[self.current].url, &self.config.block)
+                    .await
+                    .is_err()
+            {
+                println!(
+                    "Requests failed reading from RPC endpoint #{}",
+                    self.current
+                );
+                if let Err(e) = self.rotate(&executor_mutex).await {
+                    println!("Error switching RPC endpoint: {}", e);
+                }
+                continue;
+            }
+
+            // Pinned blocks never move, but are polled all the same to notice a dead endpoint
+            let url = &self.endpoints[self.current].url;
+            let polled = block_number(url, &self.config.block).await;
+            if let Ok(block_number) = &polled {
+                self.record_poll(*block_number).await;
+            }
+            match polled {
+                Ok(block_number) if block_number == self.block_number => {}
+                Ok(block_number) => {
+                    // Fetched before taking the lock, so requests are not held up meanwhile
+                    let prefetched = self.prefetch(block_number).await;
+                    let mut executor = executor_mutex.lock().await;
+                    let fork_id = self.fork_id.or(executor.backend.active_fork_id());
+                    let _ = executor.backend.roll_fork(
+                        fork_id,
+                        block_number,
+                        &mut self.env,
+                        &mut journaled_state(),
+                    );
+                    self.block_number = block_number;
+                    self.status
+                        .block
+                        .store(block_number.to::<u64>(), Ordering::Relaxed);
+                    self.clear_responses();
+                    if let Err(e) = self.warm_cache(&mut executor) {
+                        println!("Error loading fork cache: {}", e);
+                    }
+                    if let Some(Err(e)) = prefetched.map(|p| p.apply(&mut executor)) {
+                        println!("Error loading prefetched state: {}", e);
+                    }
+                    self.on_block(&mut executor);
+                }
+                Err(e) => {
+                    println!("Error getting latest block: {}", e);
+                    if let Err(e) = self.rotate(&executor_mutex).await {
+                        println!("Error switching RPC endpoint: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves the fork to the next endpoint. Persistent accounts, such as the index
+    /// contracts, are carried over to the new fork. Each endpoint gets at most one fork, which
+    /// is rolled to the current block when rotating back to it. Named forks are not rotated,
+    /// since handlers refer to them by id.
+    async fn rotate(&mut self, executor_mutex: &Arc<Mutex<Executor>>) -> Result<(), ErrReport> {
+        if self.endpoints.len() < 2 || self.fork_id.is_some() {
+            return Ok(());
+        }
+        let previous = self.current;
+        self.current = (self.current + 1) % self.endpoints.len();
+
+        let url = self.endpoints[self.current].url.clone();
+        let block_number = block_number(&url, &self.config.block).await?;
+        let fork = CreateFork {
+            url,
+            enable_caching: false,
+            env: self.env.clone(),
+            evm_opts: self.endpoint_opts(self.current, block_number),
+        };
+
+        let mut executor = executor_mutex.lock().await;
+        self.forks[previous] = executor.backend.active_fork_id();
+        match self.forks[self.current] {
+            Some(fork_id) => {
+                executor
+                    .backend
+                    .select_fork(fork_id, &mut self.env, &mut journaled_state())?;
+                executor.backend.roll_fork(
+                    Some(fork_id),
+                    block_number,
+                    &mut self.env,
+                    &mut journaled_state(),
+                )?;
+            }
+            None => {
+                let fork_id = executor.backend.create_fork(fork)?;
+                executor
+                    .backend
+                    .select_fork(fork_id, &mut self.env, &mut journaled_state())?;
+                self.forks[self.current] = Some(fork_id);
+            }
+        }
+        self.block_number = block_number;
+        self.status
+            .block
+            .store(block_number.to::<u64>(), Ordering::Relaxed);
+        self.clear_responses();
+        self.warm_cache(&mut executor)?;
+
+        println!("Switched to RPC endpoint #{}", self.current);
+        Ok(())
+    }
+
+    /// Fetches the declared slots, and those read at the current block if enabled, at the
+    /// given block.
+    async fn prefetch(&self, block_number: U256) -> Option<Prefetched> {
+        let mut list = PrefetchList::new();
+        if let Some(route_table) = &self.route_table {
+            for entry in route_table.read().unwrap().prefetch.iter() {
+                list.entry(entry.account)
+                    .or_default()
+                    .extend(entry.slots.iter().map(|slot| U256::from_be_bytes(slot.0)));
+            }
+        }
+        if let (true, Some(cache)) = (self.config.prefetch_accessed, &self.cache) {
+            cache.lock().unwrap().accessed(&mut list);
+        }
+        if list.is_empty() {
+            return None;
+        }
+
+        let url = &self.endpoints[self.current].url;
+        match prefetch::fetch(url, block_number.to::<u64>(), list).await {
+            Ok(prefetched) => Some(prefetched),
+            Err(e) => {
+                println!("Error prefetching state: {}", e);
+                None
+            }
+        }
+    }
+
+    fn on_block(&self, executor: &mut Executor) {
+        let Some(route_table) = &self.route_table else {
+            return;
+        };
+        let addresses: Vec<Address> = route_table
+            .read()
+            .unwrap()
+            .routes
+            .iter()
+            .filter(|route| route.implements("onBlock"))
+            .map(|route| route.address)
+            .collect();
+        let caches = Caches {
+            fork: self.cache.clone(),
+            responses: self.response_cache.clone(),
+        };
+        for address in addresses {
+            match project::call_hook(executor, &caches, address, &onBlockCall {}) {
+                Ok(output) => self.events.publish(address, &output.logs),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    /// Moves the cache to the current block and loads whatever it holds for it into the fork.
+    fn warm_cache(&self, executor: &mut Executor) -> Result<(), ErrReport> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+        let mut cache = cache.lock().unwrap();
+        cache.set_block(self.block_number.to::<u64>())?;
+        cache.warm(executor)
+    }
+
+    // Finalized and safe blocks trail the chain, whose head takes another request
+    async fn record_poll(&self, block: U256) {
+        let head = match self.config.block {
+            ForkBlock::Latest => Ok(block),
+            _ => block_number(&self.endpoints[self.current].url, &ForkBlock::Latest).await,
+        };
+        if let Ok(head) = head {
+            self.status.head.store(head.to::<u64>(), Ordering::Relaxed);
+            self.status.polled.store(unix_time(), Ordering::Relaxed);
+        }
+    }
+
+    fn clear_responses(&self) {
+        if let Some(response_cache) = &self.response_cache {
+            response_cache.clear();
+        }
+    }
+
+    fn flush_cache(&self) {
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.lock().unwrap().flush() {
+                println!("Error writing fork cache: {}", e);
+            }
+        }
+    }
+}
+
+/// Chain id reported by the endpoint, cache files are kept per chain.
+pub async fn chain_id(url: &str) -> Result<u64, ErrReport> {
+    let provider = Provider::try_from(url).map_err(|e| eyre::eyre!("{}", e))?;
+    let chain_id = provider
+        .get_chain_id()
+        .await
+        .map_err(|e| eyre::eyre!("{}", e))?;
+    Ok(chain_id.to::<u64>())
+}
+
+/// Number of the block the fork should be at, according to the endpoint.
+pub async fn block_number(url: &str, block: &ForkBlock) -> Result<U256, ErrReport> {
+    let provider = Provider::try_from(url).map_err(|e| eyre::eyre!("{}", e))?;
+    let tag = match block {
+        ForkBlock::Number(_) | ForkBlock::Latest => {
+            // Pinned blocks are known, asking for the latest one tells the endpoint is alive
+            let latest = provider
+                .get_block_number()
+                .await
+                .map_err(|e| eyre::eyre!("{}", e))?;
+            return Ok(match block {
+                ForkBlock::Number(number) => U256::from(*number),
+                _ => U256::from(latest),
+            });
+        }
+        ForkBlock::Finalized => BlockNumberOrTag::Finalized,
+        ForkBlock::Safe => BlockNumberOrTag::Safe,
+    };
+    provider
+        .get_block_by_number(tag, false)
+        .await
+        .map_err(|e| eyre::eyre!("{}", e))?
+        .and_then(|block| block.header.number)
+        .ok_or_else(|| eyre::eyre!("RPC did not return the {:?} block", block))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+pub fn journaled_state() -> JournaledState {
+    JournaledState::new(
+        SpecId::CANCUN,
+        Precompiles::new(PrecompileSpec::CANCUN)
+            .addresses()
+            .into_iter()
+            .copied()
+            .collect(),
+    )
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use alloy_primitives::{Address, Bytes as EvmBytes, U256, U64};
+use eyre::{Context, ErrReport, Result};
+use forge::{
+    executors::Executor,
+    revm::primitives::{AccountInfo, Bytecode},
+};
+use serde::Deserialize;
+
+/// Either a full genesis file or just its `alloc` section.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AllocFile {
+    Genesis {
+        alloc: BTreeMap<Address, GenesisAccount>,
+    },
+    Alloc(BTreeMap<Address, GenesisAccount>),
+}
+
+#[derive(Deserialize)]
+struct GenesisAccount {
+    #[serde(default)]
+    balance: U256,
+    #[serde(default)]
+    nonce: U64,
+    code: Option<EvmBytes>,
+    #[serde(default)]
+    storage: BTreeMap<U256, U256>,
+}
+
+/// Seeds the executor with the accounts of a genesis allocation file.
+pub fn apply_alloc(executor: &mut Executor, path: &str) -> Result<(), ErrReport> {
+    let content =
+        fs::read_to_string(path).wrap_err_with(|| format!("Failed to read alloc file {path}"))?;
+    let alloc = match serde_json::from_str(&content)
+        .wrap_err_with(|| format!("Failed to parse alloc file {path}"))?
+    {
+        AllocFile::Genesis { alloc } => alloc,
+        AllocFile::Alloc(alloc) => alloc,
+    };
+
+    for (address, account) in alloc {
+        let mut info = AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce.to::<u64>(),
+            ..Default::default()
+        };
+        if let Some(code) = account.code.filter(|code| !code.is_empty()) {
+            let code = Bytecode::new_raw(code);
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        }
+        executor.backend.insert_account_info(address, info);
+        // Rolling or switching forks would otherwise drop the allocation
+        executor.backend.add_persistent_account(address);
+
+        for (slot, value) in account.storage {
+            executor
+                .backend
+                .insert_account_storage(address, slot, value)?;
+        }
+    }
+
+    println!("Loaded genesis allocation from {}", path);
+    Ok(())
+}
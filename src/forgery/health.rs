@@ -0,0 +1,138 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use hyper::{header::CONTENT_TYPE, Method, Request, Response, StatusCode};
+use serde_json::json;
+
+use crate::forgery::{
+    body::{self, ResponseBody},
+    config::HealthConfig,
+    fork::ForkStatus,
+    routes::RouteTable,
+};
+
+/// Serves the health, readiness and info endpoints. They only read shared state, never
+/// the executor, so they answer while a request is being served, and while starting up.
+pub struct Health {
+    config: HealthConfig,
+    /// Empty until the index contracts are deployed
+    route_table: Arc<RwLock<RouteTable>>,
+    /// Status of the primary fork, set once it's connected and never offline
+    fork: OnceLock<Arc<ForkStatus>>,
+    /// Pinned forks never move, so they can't fall behind
+    pinned: bool,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig, route_table: Arc<RwLock<RouteTable>>, pinned: bool) -> Self {
+        Health {
+            config,
+            route_table,
+            fork: OnceLock::new(),
+            pinned,
+        }
+    }
+
+    /// Starts reporting on the primary fork.
+    pub fn set_fork(&self, fork: Arc<ForkStatus>) {
+        let _ = self.fork.set(fork);
+    }
+
+    /// Response to a request for one of the endpoints, `None` for any other request.
+    pub fn serve<B>(&self, req: &Request<B>) -> Option<Response<ResponseBody>> {
+        if !self.config.enabled || (req.method() != Method::GET && req.method() != Method::HEAD) {
+            return None;
+        }
+        let response = self.endpoint(req)?;
+        if req.method() == Method::HEAD {
+            return Some(response.map(|_| body::full("")));
+        }
+        Some(response)
+    }
+
+    fn endpoint<B>(&self, req: &Request<B>) -> Option<Response<ResponseBody>> {
+        let endpoint = req
+            .uri()
+            .path()
+            .strip_prefix(self.config.prefix.trim_end_matches('/'))?;
+        match endpoint {
+            "/health" => Some(respond(StatusCode::OK, json!({ "status": "ok" }))),
+            "/ready" => {
+                let problems = self.problems();
+                let status = if problems.is_empty() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Some(respond(
+                    status,
+                    json!({ "ready": problems.is_empty(), "problems": problems }),
+                ))
+            }
+            "/info" => Some(respond(StatusCode::OK, self.info())),
+            _ => None,
+        }
+    }
+
+    // Reasons the server shouldn't receive traffic
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.route_table.read().unwrap().routes.is_empty() {
+            problems.push("No index contract is deployed".to_string());
+        }
+        let Some(fork) = self.fork.get() else {
+            return problems;
+        };
+        match fork.since_poll() {
+            Some(age) if age <= self.config.max_poll_age => {}
+            Some(age) => problems.push(format!("RPC last answered {age}s ago")),
+            None => problems.push("RPC not polled yet".to_string()),
+        }
+        if let (Some(max_lag), Some(lag), false) = (self.config.max_lag, fork.lag(), self.pinned) {
+            if lag > max_lag {
+                problems.push(format!("Fork is {lag} blocks behind"));
+            }
+        }
+        problems
+    }
+
+    fn info(&self) -> serde_json::Value {
+        let routes: Vec<serde_json::Value> = self
+            .route_table
+            .read()
+            .unwrap()
+            .routes
+            .iter()
+            .map(|route| {
+                json!({
+                    "prefix": route.config.prefix,
+                    "host": route.config.host,
+                    "contract": route.identifier,
+                    "address": route.address,
+                })
+            })
+            .collect();
+        let fork = self.fork.get().map(|fork| {
+            json!({
+                "block": fork.block(),
+                "head": fork.head(),
+                "lag": fork.lag(),
+                "pinned": self.pinned,
+                "since_poll": fork.since_poll(),
+            })
+        });
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "deployed": !routes.is_empty(),
+            "routes": routes,
+            "fork": fork,
+        })
+    }
+}
+
+fn respond(status: StatusCode, value: serde_json::Value) -> Response<ResponseBody> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body::full(value.to_string()))
+        .unwrap()
+}
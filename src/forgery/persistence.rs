@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use alloy_primitives::{Address, U256};
+use eyre::{Context, ErrReport, Result};
+use forge::{backend::Backend, executors::Executor, revm::db::DbAccount};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::forgery::{config::PersistenceConfig, routes::RouteTable};
+
+/// Balance and storage of persisted accounts, written to disk as JSON.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    accounts: BTreeMap<Address, AccountSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountSnapshot {
+    balance: U256,
+    storage: BTreeMap<U256, U256>,
+}
+
+impl Snapshot {
+    /// Reads a snapshot, or returns an empty one if none was written yet.
+    pub fn load(path: &str) -> Result<Self, ErrReport> {
+        if !Path::new(path).exists() {
+            return Ok(Default::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).wrap_err_with(|| format!("Failed to parse snapshot {path}"))
+    }
+
+    pub fn capture(executor: &Executor, accounts: &[Address]) -> Self {
+        let accounts = accounts
+            .iter()
+            .filter_map(|address| {
+                let account = cached_account(&executor.backend, *address)?;
+                Some((
+                    *address,
+                    AccountSnapshot {
+                        balance: account.info.balance,
+                        storage: account.storage.clone().into_iter().collect(),
+                    },
+                ))
+            })
+            .collect();
+        Snapshot { accounts }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ErrReport> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so a crash never leaves a truncated snapshot
+        let tmp_path = format!("{path}.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Writes the persisted balance and storage of an account back into the executor.
+    pub fn restore(&self, executor: &mut Executor, address: Address) -> Result<(), ErrReport> {
+        // Restored state must survive fork switches and rolls, like the index contracts
+        executor.backend.add_persistent_account(address);
+        let Some(account) = self.accounts.get(&address) else {
+            return Ok(());
+        };
+        executor.set_balance(address, account.balance)?;
+        for (slot, value) in account.storage.iter() {
+            executor
+                .backend
+                .insert_account_storage(address, *slot, *value)?;
+        }
+        println!("Restored state of {}", address);
+        Ok(())
+    }
+}
+
+// Committed state lives in the cache of the active fork, or in the in-memory db without one
+fn cached_account(backend: &Backend, address: Address) -> Option<&DbAccount> {
+    match backend.active_fork_db() {
+        Some(db) => db.accounts.get(&address),
+        None => backend.mem_db().accounts.get(&address),
+    }
+}
+
+/// Periodically snapshots the index contracts and configured accounts. A final snapshot is
+/// taken on Ctrl-C before exiting.
+pub async fn run(
+    config: PersistenceConfig,
+    executor_mutex: Arc<Mutex<Executor>>,
+    route_table: Arc<RwLock<RouteTable>>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    loop {
+        let shutdown = tokio::select! {
+            _ = interval.tick() => false,
+            _ = tokio::signal::ctrl_c() => true,
+        };
+
+        let executor = executor_mutex.lock().await;
+        let mut accounts = config.accounts.clone();
+        accounts.extend(
+            route_table
+                .read()
+                .unwrap()
+                .routes
+                .iter()
+                .map(|route| route.address),
+        );
+        let snapshot = Snapshot::capture(&executor, &accounts);
+        drop(executor);
+
+        if let Err(err) = snapshot.save(&config.path) {
+            println!("Failed to save snapshot: {}", err);
+        }
+
+        if shutdown {
+            std::process::exit(0);
+        }
+    }
+}
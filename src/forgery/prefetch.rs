@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use alloy_primitives::{Address, Bytes, U256};
+use eyre::{ErrReport, Result};
+use forge::{
+    executors::Executor,
+    revm::primitives::{AccountInfo, Bytecode},
+};
+use foundry_evm_core::backend::DatabaseExt;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes as HyperBytes, header, Request};
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Calls sent in a single JSON-RPC batch, some providers reject larger batches
+const BATCH_SIZE: usize = 100;
+
+/// Storage slots to fetch for each account. Accounts are always fetched, even without slots.
+pub type PrefetchList = BTreeMap<Address, BTreeSet<U256>>;
+
+enum Query {
+    Balance(Address),
+    Nonce(Address),
+    Code(Address),
+    Slot(Address, U256),
+}
+
+impl Query {
+    fn call(&self, id: usize, block: &str) -> Value {
+        let (method, params) = match self {
+            Query::Balance(address) => ("eth_getBalance", json!([address, block])),
+            Query::Nonce(address) => ("eth_getTransactionCount", json!([address, block])),
+            Query::Code(address) => ("eth_getCode", json!([address, block])),
+            Query::Slot(address, slot) => ("eth_getStorageAt", json!([address, slot, block])),
+        };
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    id: usize,
+    result: Option<Value>,
+    error: Option<Value>,
+}
+
+/// State fetched from the RPC at a given block, ready to be loaded into the fork.
+#[derive(Default)]
+pub struct Prefetched {
+    accounts: BTreeMap<Address, AccountInfo>,
+    storage: Vec<(Address, U256, U256)>,
+}
+
+/// Fetches every account and slot of the list at a block, using JSON-RPC batch requests of up
+/// to `BATCH_SIZE` calls each.
+pub async fn fetch(url: &str, block: u64, list: PrefetchList) -> Result<Prefetched, ErrReport> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+    let block = format!("0x{:x}", block);
+
+    let queries: Vec<Query> = list
+        .iter()
+        .flat_map(|(address, _)| {
+            [
+                Query::Balance(*address),
+                Query::Nonce(*address),
+                Query::Code(*address),
+            ]
+        })
+        .chain(
+            list.iter()
+                .flat_map(|(address, slots)| slots.iter().map(|slot| Query::Slot(*address, *slot))),
+        )
+        .collect();
+
+    let mut prefetched = Prefetched::default();
+    for batch in queries.chunks(BATCH_SIZE) {
+        let calls: Vec<Value> = batch
+            .iter()
+            .enumerate()
+            .map(|(id, query)| query.call(id, &block))
+            .collect();
+        let request = Request::post(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(HyperBytes::from(serde_json::to_vec(&calls)?)))?;
+        let response = client.request(request).await?;
+        let body = response.into_body().collect().await?.to_bytes();
+        // Providers without batch support answer with a single error object
+        let responses: Vec<RpcResponse> = serde_json::from_slice(&body).map_err(|_| {
+            eyre::eyre!(
+                "RPC did not answer the batch request: {}",
+                String::from_utf8_lossy(&body)
+            )
+        })?;
+
+        // Answers may come in any order, and are matched to their call by id
+        for response in responses {
+            let query = batch
+                .get(response.id)
+                .ok_or_else(|| eyre::eyre!("RPC answered unknown call {}", response.id))?;
+            let result = match (response.result, response.error) {
+                (Some(result), None) => result,
+                (_, error) => eyre::bail!("RPC call failed: {}", error.unwrap_or_default()),
+            };
+            prefetched.insert(query, result)?;
+        }
+    }
+    Ok(prefetched)
+}
+
+impl Prefetched {
+    fn insert(&mut self, query: &Query, result: Value) -> Result<(), ErrReport> {
+        match query {
+            Query::Balance(address) => {
+                self.accounts.entry(*address).or_default().balance =
+                    serde_json::from_value(result)?;
+            }
+            Query::Nonce(address) => {
+                let nonce: U256 = serde_json::from_value(result)?;
+                self.accounts.entry(*address).or_default().nonce = nonce.to::<u64>();
+            }
+            Query::Code(address) => {
+                let code: Bytes = serde_json::from_value(result)?;
+                if !code.is_empty() {
+                    let code = Bytecode::new_raw(code);
+                    let info = self.accounts.entry(*address).or_default();
+                    info.code_hash = code.hash_slow();
+                    info.code = Some(code);
+                }
+            }
+            Query::Slot(address, slot) => {
+                self.storage
+                    .push((*address, *slot, serde_json::from_value(result)?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the fetched state into the active fork. Persistent accounts hold local state
+    /// and are left untouched.
+    pub fn apply(self, executor: &mut Executor) -> Result<(), ErrReport> {
+        for (address, info) in self.accounts {
+            if !executor.backend.is_persistent(&address) {
+                executor.backend.insert_account_info(address, info);
+            }
+        }
+        for (address, slot, value) in self.storage {
+            if !executor.backend.is_persistent(&address) {
+                executor
+                    .backend
+                    .insert_account_storage(address, slot, value)?;
+            }
+        }
+        Ok(())
+    }
+}
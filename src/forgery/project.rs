@@ -1,16 +1,23 @@
 use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex as StdMutex};
 
+use alloy_dyn_abi::{DynSolValue, JsonAbiExt, Specifier};
+use alloy_json_abi::JsonAbi;
 use alloy_primitives::{Address, Bytes as EvmBytes, U256};
 use alloy_sol_types::SolCall;
 use eyre::{Context, ContextCompat, ErrReport, Result};
 use forge::{
     backend::{Backend, DatabaseExt},
-    executors::{DeployResult, Executor, ExecutorBuilder},
+    decode::decode_console_logs,
+    executors::{Executor, ExecutorBuilder},
     fork::CreateFork,
     inspectors::CheatsConfig,
     link::{link_with_nonce_or_address, PostLinkInput, ResolvedDependency},
     opts::EvmOpts,
-    revm::primitives::{db::DatabaseCommit, Env as RevmEnv, SpecId},
+    revm::{
+        primitives::{db::DatabaseCommit, Env as RevmEnv, Log, SpecId, State, KECCAK_EMPTY},
+        DatabaseRef,
+    },
 };
 use foundry_common::{
     compact_to_contract,
@@ -23,19 +30,26 @@ use foundry_compilers::{
     ArtifactId, Project,
 };
 use foundry_config::Config;
+use foundry_evm_core::constants::DEFAULT_CREATE2_DEPLOYER;
 
-use crate::forgery::types::startCall;
+use crate::forgery::{
+    cache::ForkCache,
+    config::DeployConfig,
+    response_cache::{self, ResponseCache},
+    types::{configureCall, prefetchCall, startCall, SolConfigEntry, SolPrefetch},
+};
 
 pub async fn executor(opts: EvmOpts, revm_env: RevmEnv) -> Result<Executor, ErrReport> {
     // The db backend that serves all the data.
-    let fork_url = opts.fork_url.clone().expect("DB fork url is missing");
-    let fork = CreateFork {
+    // Without a fork url, the backend is an empty in-memory db
+    let fork = opts.fork_url.clone().map(|fork_url| CreateFork {
         url: fork_url,
-        enable_caching: true,
+        // Forgery keeps its own cache of the fork state, see `cache::ForkCache`
+        enable_caching: false,
         env: revm_env.clone(),
         evm_opts: opts.clone(),
-    };
-    let db = Backend::spawn(Some(fork)).await;
+    });
+    let db = Backend::spawn(fork).await;
     let config = Config::load();
 
     let builder = ExecutorBuilder::new()
@@ -51,12 +65,15 @@ pub async fn executor(opts: EvmOpts, revm_env: RevmEnv) -> Result<Executor, ErrR
     Ok(builder.build(revm_env, db))
 }
 
-pub fn build() -> Result<BuildOutput, ErrReport> {
+pub fn build(
+    index_path: &str,
+    contract_name: Option<&str>,
+    sender: Address,
+    nonce: u64,
+) -> Result<BuildOutput, ErrReport> {
     let config = Config::load();
     let project = config.project().unwrap();
 
-    let index_path = "./src/Index.sol";
-
     let target_result = canonicalize_path(index_path);
 
     if let Err(err) = target_result {
@@ -107,7 +124,7 @@ pub fn build() -> Result<BuildOutput, ErrReport> {
     let mut contract = CompactContractBytecode::default();
     let mut highlevel_known_contracts = BTreeMap::new();
 
-    let target_fname = canonicalize_path(index_path)
+    let mut target_fname = canonicalize_path(index_path)
         .wrap_err("Couldn't convert contract path to absolute path.")?
         .strip_prefix(project.root())
         .wrap_err("Couldn't strip project root from contract path.")?
@@ -115,7 +132,10 @@ pub fn build() -> Result<BuildOutput, ErrReport> {
         .wrap_err("Bad path to string.")?
         .to_string();
 
-    let no_target_name = true;
+    let no_target_name = contract_name.is_none();
+    if let Some(name) = contract_name {
+        target_fname = format!("{target_fname}:{name}");
+    }
 
     let mut extra_info = ExtraLinkingInfo {
         no_target_name,
@@ -141,8 +161,8 @@ pub fn build() -> Result<BuildOutput, ErrReport> {
         contracts.clone(),
         &mut highlevel_known_contracts,
         libs,
-        Address::ZERO,
-        0u64,
+        sender,
+        nonce,
         &mut extra_info,
         |post_link_input| {
             let PostLinkInput {
@@ -232,58 +252,270 @@ pub fn build() -> Result<BuildOutput, ErrReport> {
         sources: Default::default(),
         project,
         libraries: new_libraries,
+        nonce,
     })
 }
 
-pub fn deploy(executor: &mut Executor, build: BuildOutput) -> Result<Address, ErrReport> {
-    let CompactContractBytecode { bytecode, .. } = build.contract;
-    executor.set_nonce(Address::ZERO, 0)?;
+pub fn deploy(
+    executor: &mut Executor,
+    build: BuildOutput,
+    config: &DeployConfig,
+) -> Result<Deployment, ErrReport> {
+    let sender = config.sender;
+    let CompactContractBytecode { abi, bytecode, .. } = build.contract;
+    // Libraries were linked against this nonce, deployment must start from it
+    executor.set_nonce(sender, build.nonce)?;
 
     // We max out their balance so that they can deploy and make calls.
-    executor.set_balance(Address::ZERO, U256::MAX)?;
+    executor.set_balance(sender, U256::MAX)?;
 
     // Deploy libraries
-    build.predeploy_libraries.iter().for_each(|code| {
+    for code in build.predeploy_libraries.iter() {
         executor
-            .deploy(Address::ZERO, code.clone(), U256::ZERO, None)
-            .expect("couldn't deploy library");
-    });
+            .deploy(sender, code.clone(), U256::ZERO, None)
+            .map_err(|err| eyre::eyre!("Couldn't deploy library:\n{}", err))?;
+    }
 
-    let address = Address::ZERO.create(executor.get_nonce(Address::ZERO)?);
+    let mut init_code = bytecode
+        .wrap_err("No bytecode")?
+        .into_bytes()
+        .wrap_err("Contract bytecode is not linked")?
+        .to_vec();
+    init_code.extend(encode_constructor_args(abi.as_ref(), &config.args)?);
+
+    let address = match config.salt {
+        Some(salt) => DEFAULT_CREATE2_DEPLOYER.create2_from_code(salt, &init_code),
+        None => sender.create(executor.get_nonce(sender)?),
+    };
 
     // Set the contracts initial balance before deployment, so it is available during the
     // construction
-    executor.set_balance(address, U256::MAX)?;
+    executor.set_balance(address, config.balance)?;
 
     // Deploy an instance of the contract
-    let DeployResult { address, .. } = executor
-        .deploy(
-            Address::ZERO,
-            bytecode.expect("No bytecode").into_bytes().unwrap(),
-            U256::ZERO,
-            None,
-        )
-        .map_err(|err| eyre::eyre!("Failed to deploy script:\n{}", err))?;
+    match config.salt {
+        Some(salt) => {
+            if executor
+                .backend
+                .basic_ref(DEFAULT_CREATE2_DEPLOYER)?
+                .map_or(true, |info| info.code_hash == KECCAK_EMPTY)
+            {
+                eyre::bail!("CREATE2 deployer not found at {}", DEFAULT_CREATE2_DEPLOYER);
+            }
+            let calldata = [salt.as_slice(), &init_code].concat();
+            let res = executor
+                .call_raw(
+                    sender,
+                    DEFAULT_CREATE2_DEPLOYER,
+                    calldata.into(),
+                    config.value,
+                )
+                .map_err(|err| eyre::eyre!("Failed to deploy script:\n{}", err))?;
+            if res.reverted {
+                eyre::bail!("CREATE2 deployment reverted with: {:#?}", res.exit_reason);
+            }
+            if let Some(changes) = &res.state_changeset {
+                executor.backend.commit(changes.clone());
+            }
+        }
+        None => {
+            executor
+                .deploy(sender, init_code.into(), config.value, None)
+                .map_err(|err| eyre::eyre!("Failed to deploy script:\n{}", err))?;
+        }
+    }
 
     executor.backend.add_persistent_account(address);
 
+    Ok(Deployment { address, abi })
+}
+
+/// Runs the initialization hooks of a freshly deployed index contract.
+pub fn start(
+    executor: &mut Executor,
+    deployment: &Deployment,
+    sender: Address,
+    settings: &[SolConfigEntry],
+) -> Result<(), ErrReport> {
+    let address = deployment.address;
+
+    // configure() is optional, only call it if the contract implements it
+    if deployment.implements("configure") {
+        let fn_call = configureCall {
+            _0: settings.to_vec(),
+        };
+        let calldata = fn_call.abi_encode();
+        let res = executor
+            .call_raw(sender, address, calldata.into(), U256::ZERO)
+            .map_err(|err| {
+                eyre::eyre!("Error occured while trying to execute configure(): {}", err)
+            })?;
+
+        if res.reverted {
+            eyre::bail!("configure() call reverted with: {:#?}", res.exit_reason);
+        }
+
+        if let Some(changes) = &res.state_changeset {
+            executor.backend.commit(changes.clone());
+        }
+    }
+
     let fn_call = startCall {};
     let calldata = fn_call.abi_encode();
-    let call = executor.call_raw(Address::ZERO, address, calldata.into(), U256::ZERO);
-    let res = call
-        .map_err(|err| {
-            panic!("Error occured while trying to execute start(): {}", err);
-        })
-        .unwrap();
+    let call = executor.call_raw(sender, address, calldata.into(), U256::ZERO);
+    let res =
+        call.map_err(|err| eyre::eyre!("Error occured while trying to execute start(): {}", err))?;
 
     if res.reverted {
-        panic!("start() call reverted with: {:#?}", res.exit_reason);
+        eyre::bail!("start() call reverted with: {:#?}", res.exit_reason);
     }
 
     if let Some(changes) = &res.state_changeset {
         executor.backend.commit(changes.clone());
     }
-    Ok(address)
+    Ok(())
+}
+
+/// Caches that follow the state committed by the calls serving clients.
+#[derive(Clone, Default)]
+pub struct Caches {
+    /// Records the state calls read from the fork
+    pub fork: Option<Arc<StdMutex<ForkCache>>>,
+    /// Cleared whenever a call writes to state
+    pub responses: Option<Arc<ResponseCache>>,
+}
+
+impl Caches {
+    /// Commits the changes of a call, returning whether it wrote to state.
+    pub fn commit(&self, executor: &mut Executor, changes: &State) -> bool {
+        if let Some(cache) = &self.fork {
+            cache.lock().unwrap().record(&executor.backend, changes);
+        }
+        // Handlers are called from the zero address, whose nonce doesn't matter to them
+        let writes = response_cache::writes_state(&executor.backend, changes, Address::ZERO);
+        if let (true, Some(responses)) = (writes, &self.responses) {
+            responses.clear();
+        }
+        executor.backend.commit(changes.clone());
+        writes
+    }
+}
+
+/// Outcome of a hook call.
+pub struct HookOutput<R> {
+    pub returns: R,
+    pub logs: Vec<Log>,
+    pub gas_used: u64,
+}
+
+/// Runs a hook of an index contract outside of a request, such as `onBlock()`, committing
+/// its changes. Returns the decoded return values along with the logs it emitted.
+pub fn call_hook<C: SolCall>(
+    executor: &mut Executor,
+    caches: &Caches,
+    address: Address,
+    call: &C,
+) -> Result<HookOutput<C::Return>, ErrReport> {
+    let calldata = call.abi_encode();
+    let res = executor
+        .call_raw(Address::ZERO, address, calldata.into(), U256::ZERO)
+        .map_err(|err| {
+            eyre::eyre!(
+                "Error occured while trying to execute {}: {}",
+                C::SIGNATURE,
+                err
+            )
+        })?;
+
+    for log in decode_console_logs(&res.logs) {
+        println!("{}", log);
+    }
+    if res.reverted {
+        eyre::bail!(
+            "{} call reverted with: {:#?}",
+            C::SIGNATURE,
+            res.exit_reason
+        );
+    }
+
+    if let Some(changes) = &res.state_changeset {
+        caches.commit(executor, changes);
+    }
+    let returns = C::abi_decode_returns(&res.result, true)?;
+    Ok(HookOutput {
+        returns,
+        logs: res.logs,
+        gas_used: res.gas_used,
+    })
+}
+
+/// Accounts and storage slots the index contract wants fetched ahead of requests.
+pub fn prefetch_list(
+    executor: &mut Executor,
+    deployment: &Deployment,
+) -> Result<Vec<SolPrefetch>, ErrReport> {
+    // prefetch() is optional as well
+    if !deployment.implements("prefetch") {
+        return Ok(Vec::new());
+    }
+    let calldata = prefetchCall {}.abi_encode();
+    let res = executor
+        .call_raw(
+            Address::ZERO,
+            deployment.address,
+            calldata.into(),
+            U256::ZERO,
+        )
+        .map_err(|err| eyre::eyre!("Error occured while trying to execute prefetch(): {}", err))?;
+
+    if res.reverted {
+        eyre::bail!("prefetch() call reverted with: {:#?}", res.exit_reason);
+    }
+    Ok(prefetchCall::abi_decode_returns(&res.result, true)?._0)
+}
+
+fn encode_constructor_args(abi: Option<&JsonAbi>, args: &[String]) -> Result<Vec<u8>, ErrReport> {
+    let Some(constructor) = abi.and_then(|abi| abi.constructor.as_ref()) else {
+        if !args.is_empty() {
+            eyre::bail!("Constructor arguments were given but the contract has no constructor");
+        }
+        return Ok(Vec::new());
+    };
+
+    if constructor.inputs.len() != args.len() {
+        eyre::bail!(
+            "Constructor expects {} arguments, got {}",
+            constructor.inputs.len(),
+            args.len()
+        );
+    }
+
+    let values = constructor
+        .inputs
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| {
+            param.resolve()?.coerce_str(arg).wrap_err_with(|| {
+                format!("Invalid value for constructor argument `{}`", param.name)
+            })
+        })
+        .collect::<Result<Vec<DynSolValue>>>()?;
+
+    Ok(constructor.abi_encode_input(&values)?)
+}
+
+pub struct Deployment {
+    pub address: Address,
+    pub abi: Option<JsonAbi>,
+}
+
+impl Deployment {
+    /// Whether the contract exposes a function, used for optional Core API hooks
+    pub fn implements(&self, name: &str) -> bool {
+        self.abi
+            .as_ref()
+            .map_or(false, |abi| abi.functions.contains_key(name))
+    }
 }
 
 struct ExtraLinkingInfo<'a> {
@@ -304,4 +536,5 @@ pub struct BuildOutput {
     pub libraries: Libraries,
     pub predeploy_libraries: Vec<EvmBytes>,
     pub sources: ContractSources,
+    pub nonce: u64,
 }
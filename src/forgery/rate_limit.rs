@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use hyper::{header::RETRY_AFTER, Request, Response, StatusCode};
+
+use crate::forgery::{
+    access::CLAIM_HEADER_PREFIX,
+    body::{self, ResponseBody},
+    config::RateLimitConfig,
+    routes,
+};
+
+/// Clients tracked at most, idle ones are forgotten first
+const MAX_CLIENTS: usize = 10_000;
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: f64) -> Self {
+        Bucket {
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.updated = now;
+    }
+
+    // Seconds until the bucket holds `tokens`
+    fn wait(&self, tokens: f64, rate: f64) -> f64 {
+        (tokens - self.tokens).max(0.0) / rate
+    }
+}
+
+struct Buckets {
+    requests: Bucket,
+    gas: Option<Bucket>,
+}
+
+/// Limits and clients a request was admitted under, which its gas is charged to.
+#[derive(Clone, Default)]
+pub struct Clients(Vec<(usize, String)>);
+
+/// Token buckets per client for every configured path prefix. Clients are identified by a
+/// verified claim when configured, by IP otherwise.
+pub struct RateLimiter {
+    limits: Vec<RateLimitConfig>,
+    trusted_proxies: Vec<IpAddr>,
+    buckets: StdMutex<HashMap<(usize, String), Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Vec<RateLimitConfig>, trusted_proxies: Vec<IpAddr>) -> Self {
+        RateLimiter {
+            limits,
+            trusted_proxies,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token from every bucket of the client that applies to the request, or
+    /// answers with a `429` if any of them is empty. Claims must be verified by then.
+    pub fn admit<B>(
+        &self,
+        peer: IpAddr,
+        req: &Request<B>,
+    ) -> Result<Clients, Response<ResponseBody>> {
+        let ip = self.client_ip(peer, req);
+        let clients: Vec<(usize, String)> = self
+            .limits
+            .iter()
+            .enumerate()
+            .filter(|(_, limit)| routes::matches_prefix(&limit.prefix, req.uri().path()))
+            .map(|(index, limit)| (index, client_key(limit, ip, req)))
+            .collect();
+        if clients.is_empty() {
+            return Ok(Clients::default());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let new = clients
+            .iter()
+            .filter(|client| !buckets.contains_key(*client))
+            .count();
+        if buckets.len() + new > MAX_CLIENTS {
+            self.forget_idle(&mut buckets, now);
+        }
+        // Busy clients past the cap make room in the order they were last seen
+        while buckets.len() + new > MAX_CLIENTS {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, client)| client.requests.updated)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => buckets.remove(&key),
+                None => break,
+            };
+        }
+
+        if let Err(wait) = self.take(&mut buckets, &clients, now) {
+            return Err(Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, wait.ceil() as u64)
+                .body(body::full("Rate limit exceeded"))
+                .unwrap());
+        }
+        Ok(Clients(clients))
+    }
+
+    /// Charges the gas a request consumed to the clients it was admitted under.
+    pub fn charge(&self, clients: &Clients, gas_used: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        for client in clients.0.iter() {
+            if let Some(bucket) = buckets.get_mut(client).and_then(|b| b.gas.as_mut()) {
+                bucket.tokens -= gas_used as f64;
+            }
+        }
+    }
+
+    // Tokens are only taken once every bucket has enough, so rejected requests cost nothing.
+    // Otherwise returns the seconds until they do.
+    fn take(
+        &self,
+        buckets: &mut HashMap<(usize, String), Buckets>,
+        clients: &[(usize, String)],
+        now: Instant,
+    ) -> Result<(), f64> {
+        let mut wait: f64 = 0.0;
+        for (index, key) in clients.iter() {
+            let limit = &self.limits[*index];
+            let client = buckets
+                .entry((*index, key.clone()))
+                .or_insert_with(|| Buckets {
+                    requests: Bucket::full(limit.burst as f64),
+                    gas: limit.gas.as_ref().map(|gas| Bucket::full(gas.burst as f64)),
+                });
+            client.requests.refill(limit.rate, limit.burst as f64, now);
+            wait = wait.max(client.requests.wait(1.0, limit.rate));
+            if let (Some(bucket), Some(gas)) = (&mut client.gas, &limit.gas) {
+                bucket.refill(gas.rate as f64, gas.burst as f64, now);
+                // Gas is charged after the call, a client in debt waits until it's paid off
+                if bucket.tokens <= 0.0 {
+                    wait = wait.max(bucket.wait(1.0, gas.rate as f64));
+                }
+            }
+        }
+        if wait > 0.0 {
+            return Err(wait);
+        }
+
+        for client in clients.iter() {
+            if let Some(buckets) = buckets.get_mut(client) {
+                buckets.requests.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    // Behind trusted proxies, the client is the closest address they forwarded for that
+    // isn't a proxy itself
+    fn client_ip<B>(&self, peer: IpAddr, req: &Request<B>) -> IpAddr {
+        let forwarded: Vec<&str> = req
+            .headers()
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let mut client = peer;
+        for hop in forwarded.iter().rev() {
+            if !self.trusted_proxies.contains(&client) {
+                break;
+            }
+            match hop.parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+
+    // Full buckets are no different from new ones, so they can be dropped
+    fn forget_idle(&self, buckets: &mut HashMap<(usize, String), Buckets>, now: Instant) {
+        buckets.retain(|(index, _), client| {
+            let limit = &self.limits[*index];
+            client.requests.refill(limit.rate, limit.burst as f64, now);
+            let gas_full = match (&mut client.gas, &limit.gas) {
+                (Some(bucket), Some(gas)) => {
+                    bucket.refill(gas.rate as f64, gas.burst as f64, now);
+                    bucket.tokens >= gas.burst as f64
+                }
+                _ => true,
+            };
+            client.requests.tokens < limit.burst as f64 || !gas_full
+        });
+    }
+}
+
+/// Limits the hook calls that follow an admitted request, such as the messages of a WebSocket
+/// or the chunks of a streamed response, under the same clients.
+#[derive(Clone)]
+pub struct Throttle {
+    limiter: Arc<RateLimiter>,
+    clients: Clients,
+}
+
+impl Throttle {
+    pub fn new(limiter: Arc<RateLimiter>, clients: Clients) -> Self {
+        Throttle { limiter, clients }
+    }
+
+    /// Takes a token from every bucket of the clients, waiting for them to refill when any
+    /// of them is empty.
+    pub async fn admit(&self) {
+        loop {
+            let taken = self.limiter.take(
+                &mut self.limiter.buckets.lock().unwrap(),
+                &self.clients.0,
+                Instant::now(),
+            );
+            match taken {
+                Ok(()) => return,
+                // A zero rate never refills
+                Err(wait) => {
+                    let wait = Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX);
+                    tokio::time::sleep(wait).await
+                }
+            }
+        }
+    }
+
+    /// Charges the gas of a hook call to the clients.
+    pub fn charge(&self, gas_used: u64) {
+        self.limiter.charge(&self.clients, gas_used);
+    }
+}
+
+// Requests without the configured claim are limited by IP
+fn client_key<B>(limit: &RateLimitConfig, ip: IpAddr, req: &Request<B>) -> String {
+    limit
+        .claim
+        .as_ref()
+        .and_then(|claim| req.headers().get(format!("{CLAIM_HEADER_PREFIX}{claim}")))
+        .and_then(|value| value.to_str().ok())
+        .map_or_else(|| format!("ip:{ip}"), |value| format!("claim:{value}"))
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+use alloy_primitives::Address;
+use forge::{
+    backend::Backend,
+    revm::{
+        primitives::{Account, State},
+        DatabaseRef,
+    },
+};
+use http_body_util::BodyExt;
+use hyper::{
+    body::Bytes as HyperBytes,
+    header::{HeaderValue, CACHE_CONTROL, HOST},
+    HeaderMap, Method, Request, Response, StatusCode,
+};
+
+use crate::forgery::{
+    access::CLAIM_HEADER_PREFIX,
+    body::{self, ResponseBody},
+    config::ResponseCacheConfig,
+};
+
+/// Identifies requests that get the same response within a block.
+#[derive(Hash, PartialEq, Eq)]
+pub struct CacheKey {
+    /// Redeployed index contracts get a new address, which leaves stale entries behind
+    address: Address,
+    method: Method,
+    host: Option<HeaderValue>,
+    uri: String,
+    headers: Vec<Option<HeaderValue>>,
+    /// Verified claims, so clients with different credentials never share responses
+    claims: Vec<(String, HeaderValue)>,
+}
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: HyperBytes,
+    expires: Option<Instant>,
+}
+
+/// Responses to `GET` and `HEAD` requests, valid until the fork moves to another block or
+/// a request writes to state.
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: StdMutex<HashMap<CacheKey, CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        ResponseCache {
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// Key of a request, `None` if its response can't be cached.
+    pub fn key<B>(&self, req: &Request<B>, address: Address) -> Option<CacheKey> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+        let mut claims: Vec<(String, HeaderValue)> = req
+            .headers()
+            .iter()
+            .filter(|(name, _)| name.as_str().starts_with(CLAIM_HEADER_PREFIX))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        claims.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(CacheKey {
+            address,
+            method: req.method().clone(),
+            host: req.headers().get(HOST).cloned(),
+            uri: req.uri().to_string(),
+            headers: self
+                .config
+                .headers
+                .iter()
+                .map(|name| req.headers().get(name.as_str()).cloned())
+                .collect(),
+            claims,
+        })
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Response<ResponseBody>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry
+            .expires
+            .map_or(false, |expires| expires <= Instant::now())
+        {
+            entries.remove(key);
+            return None;
+        }
+
+        let mut response = Response::builder()
+            .status(entry.status)
+            .body(body::full(entry.body.clone()))
+            .unwrap();
+        *response.headers_mut() = entry.headers.clone();
+        Some(response)
+    }
+
+    /// Caches a successful response, unless the handler opted out with `Cache-Control`.
+    /// `max-age` shortens how long the response is kept, which is never beyond the block.
+    pub async fn store(
+        &self,
+        key: CacheKey,
+        response: Response<ResponseBody>,
+    ) -> Response<ResponseBody> {
+        let directives = cache_directives(response.headers());
+        if response.status() != StatusCode::OK
+            || directives
+                .iter()
+                .any(|directive| ["no-store", "no-cache", "private"].contains(&directive.as_str()))
+        {
+            return response;
+        }
+        let max_age = directives
+            .iter()
+            .find_map(|directive| directive.strip_prefix("max-age="))
+            .and_then(|max_age| max_age.parse().ok())
+            .map(Duration::from_secs);
+
+        let (parts, body) = response.into_parts();
+        let content = match body.collect().await {
+            Ok(content) => content.to_bytes(),
+            Err(never) => match never {},
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() < self.config.max_entries {
+            entries.insert(
+                key,
+                CachedResponse {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: content.clone(),
+                    expires: max_age.map(|max_age| Instant::now() + max_age),
+                },
+            );
+        }
+        Response::from_parts(parts, body::full(content))
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Whether a call changed state the response to any request may depend on: contract storage,
+/// or the balance or code of an account other than the caller, such as a transfer to an index
+/// contract, a deployment or a selfdestruct. Must be checked before the changes are committed.
+pub fn writes_state(backend: &Backend, changes: &State, caller: Address) -> bool {
+    changes.iter().any(|(address, account)| {
+        account.storage.values().any(|slot| slot.is_changed())
+            || (*address != caller
+                && account.is_touched()
+                && (account.is_created()
+                    || account.is_selfdestructed()
+                    || changes_info(backend, *address, account)))
+    })
+}
+
+fn changes_info(backend: &Backend, address: Address, account: &Account) -> bool {
+    match backend.basic_ref(address) {
+        Ok(Some(info)) => {
+            info.balance != account.info.balance || info.code_hash != account.info.code_hash
+        }
+        Ok(None) => !account.info.is_empty(),
+        // Whatever changed can't be told apart, assume the worst
+        Err(_) => true,
+    }
+}
+
+fn cache_directives(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect()
+}
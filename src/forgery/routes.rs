@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+
+use alloy_primitives::Address;
+use eyre::{ErrReport, Result};
+use forge::executors::Executor;
+use hyper::{header::HOST, Request};
+
+use crate::forgery::{
+    config::{DeployConfig, RouteConfig},
+    persistence::Snapshot,
+    project::{self, BuildOutput},
+    types::{SolConfigEntry, SolPrefetch},
+};
+
+/// Optional Core API hooks
+const HOOKS: [&str; 6] = [
+    "onBlock",
+    "onConnect",
+    "onMessage",
+    "onClose",
+    "beforeServe",
+    "afterServe",
+];
+
+#[derive(Clone)]
+pub struct Route {
+    pub config: RouteConfig,
+    pub address: Address,
+    /// Identifier of the compiled index contract, `path:Name`
+    pub identifier: String,
+    /// Optional hooks the index contract implements
+    pub hooks: HashSet<String>,
+}
+
+impl Route {
+    pub fn implements(&self, hook: &str) -> bool {
+        self.hooks.contains(hook)
+    }
+}
+
+/// Maps incoming requests to the index contract that serves them.
+#[derive(Default)]
+pub struct RouteTable {
+    pub routes: Vec<Route>,
+    /// Declared by the index contracts through `prefetch()`
+    pub prefetch: Vec<SolPrefetch>,
+}
+
+impl RouteTable {
+    /// Picks the route with a matching host and the longest matching path prefix.
+    /// Routes bound to a host take precedence over catch-all ones.
+    pub fn resolve<B>(&self, req: &Request<B>) -> Option<&Route> {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| req.uri().host())
+            .map(strip_port);
+        let path = req.uri().path();
+
+        self.routes
+            .iter()
+            .filter(|route| match (&route.config.host, host) {
+                (Some(expected), Some(host)) => expected.eq_ignore_ascii_case(host),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .filter(|route| matches_prefix(&route.config.prefix, path))
+            .max_by_key(|route| (route.config.host.is_some(), route.config.prefix.len()))
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// Whether the path is the prefix or lies under it.
+pub fn matches_prefix(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Current nonce of every account deploying a route, which is where linking starts from.
+pub fn sender_nonces(
+    executor: &Executor,
+    routes: &[RouteConfig],
+) -> Result<HashMap<Address, u64>, ErrReport> {
+    routes
+        .iter()
+        .map(|route| {
+            let sender = deploy_config(route).sender;
+            Ok((sender, executor.get_nonce(sender)?))
+        })
+        .collect()
+}
+
+/// Builds the index contract of every route, linking each one against the nonce its
+/// deployment will start from.
+pub fn build(
+    routes: &[RouteConfig],
+    mut nonces: HashMap<Address, u64>,
+) -> Result<Vec<(RouteConfig, BuildOutput)>> {
+    routes
+        .iter()
+        .map(|route| {
+            let sender = deploy_config(route).sender;
+            let nonce = nonces.entry(sender).or_default();
+            let build = project::build(&route.entry, route.contract.as_deref(), sender, *nonce)?;
+            // Every library takes a nonce, and so does the contract itself
+            *nonce += build.predeploy_libraries.len() as u64 + 1;
+            Ok((route.clone(), build))
+        })
+        .collect()
+}
+
+/// Deploys the index contract of every route. Persisted state is restored before `start()`
+/// runs, so routes registered there always point at the freshly deployed code.
+pub fn deploy(
+    executor: &mut Executor,
+    builds: Vec<(RouteConfig, BuildOutput)>,
+    snapshot: Option<&Snapshot>,
+    settings: &[SolConfigEntry],
+) -> Result<RouteTable, ErrReport> {
+    let mut prefetch = Vec::new();
+    let routes = builds
+        .into_iter()
+        .map(|(config, build)| {
+            let identifier = build.target.identifier();
+            let deploy = deploy_config(&config);
+            let deployment = project::deploy(executor, build, &deploy)?;
+            if let Some(snapshot) = snapshot {
+                snapshot.restore(executor, deployment.address)?;
+            }
+            project::start(executor, &deployment, deploy.sender, settings)?;
+            prefetch.extend(project::prefetch_list(executor, &deployment)?);
+            println!(
+                "Deployed {} at {} serving {}{}",
+                identifier,
+                deployment.address,
+                config.host.as_deref().unwrap_or_default(),
+                config.prefix
+            );
+            Ok(Route {
+                config,
+                address: deployment.address,
+                identifier,
+                hooks: HOOKS
+                    .iter()
+                    .filter(|hook| deployment.implements(hook))
+                    .map(|hook| hook.to_string())
+                    .collect(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(RouteTable { routes, prefetch })
+}
+
+// `ForgeryConfig::routes()` always fills in the deploy configuration
+fn deploy_config(route: &RouteConfig) -> DeployConfig {
+    route.deploy.clone().unwrap_or_default()
+}
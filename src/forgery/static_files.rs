@@ -0,0 +1,291 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use hyper::{
+    header::{
+        HeaderValue, ACCEPT, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+        IF_NONE_MATCH, RANGE,
+    },
+    Method, Request, Response, StatusCode,
+};
+
+use crate::forgery::{
+    body::{self, ResponseBody},
+    config::StaticConfig,
+};
+
+/// Serves files from the configured directories. Requests for paths without a file fall
+/// through to the index contracts.
+pub struct StaticFiles {
+    mounts: Vec<StaticConfig>,
+}
+
+impl StaticFiles {
+    pub fn new(mut mounts: Vec<StaticConfig>) -> Self {
+        // The most specific mount is tried first
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+        StaticFiles { mounts }
+    }
+
+    pub async fn serve<B>(&self, req: &Request<B>) -> Option<Response<ResponseBody>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+        let path = req.uri().path();
+        for mount in self.mounts.iter() {
+            let Some(relative) = strip_prefix(&mount.prefix, path) else {
+                continue;
+            };
+            let Some(file) = resolve(&mount.dir, relative) else {
+                continue;
+            };
+            if let Some(response) = serve_file(req, &file).await {
+                return Some(response);
+            }
+            if mount.spa && is_page_load(req, relative) {
+                let index = Path::new(&mount.dir).join("index.html");
+                if let Some(response) = serve_file(req, &index).await {
+                    return Some(response);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn strip_prefix<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+// Maps a request path to a file inside the directory, refusing anything that escapes it.
+// Segments are decoded one by one, so encoded separators and dots can't form new ones.
+fn resolve(dir: &str, relative: &str) -> Option<PathBuf> {
+    let mut file = PathBuf::from(dir);
+    for segment in relative.split('/') {
+        let part = percent_decode(segment)?;
+        if part.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        let mut components = Path::new(&part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(part)), None) => file.push(part),
+            (Some(Component::CurDir), None) | (None, _) => {}
+            _ => return None,
+        }
+    }
+    Some(file)
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes.get(index + 1..index + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// Navigations ask for html and point at routes rather than files
+fn is_page_load<B>(req: &Request<B>, relative: &str) -> bool {
+    let accepts_html = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |accept| accept.contains("text/html"));
+    accepts_html && Path::new(relative).extension().is_none()
+}
+
+async fn serve_file<B>(req: &Request<B>, path: &Path) -> Option<Response<ResponseBody>> {
+    let mut path = path.to_path_buf();
+    let mut metadata = tokio::fs::metadata(&path).await.ok()?;
+    if metadata.is_dir() {
+        path.push("index.html");
+        metadata = tokio::fs::metadata(&path).await.ok()?;
+    }
+    if !metadata.is_file() {
+        return None;
+    }
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_secs());
+    let etag = format!("W/\"{:x}-{:x}\"", metadata.len(), modified);
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+
+    let builder = Response::builder()
+        .header(ETAG, &etag)
+        .header(CONTENT_TYPE, content_type.as_ref())
+        .header(ACCEPT_RANGES, "bytes");
+
+    let not_modified = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if not_modified {
+        return Some(
+            builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(body::full(""))
+                .unwrap(),
+        );
+    }
+
+    let content = tokio::fs::read(&path).await.ok()?;
+    let len = content.len() as u64;
+    let range = req
+        .headers()
+        .get(RANGE)
+        .map_or(ByteRange::Whole, |value| parse_range(value, len));
+    let (builder, content) = match range {
+        ByteRange::Whole => (builder.status(StatusCode::OK), content),
+        ByteRange::Partial(start, end) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+            content[start as usize..=end as usize].to_vec(),
+        ),
+        ByteRange::Unsatisfiable => {
+            return Some(
+                builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{len}"))
+                    .body(body::full(""))
+                    .unwrap(),
+            )
+        }
+    };
+
+    let builder = builder.header(CONTENT_LENGTH, content.len());
+    let body = if req.method() == Method::HEAD {
+        body::full("")
+    } else {
+        body::full(content)
+    };
+    Some(builder.body(body).unwrap())
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Whole,
+    /// Inclusive range of bytes
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Byte range asked for by a `Range` header. Headers asking for several ranges, or that
+/// can't be parsed, are ignored and the whole file is served.
+fn parse_range(value: &HeaderValue, len: u64) -> ByteRange {
+    let Some((first, last)) = parse_range_spec(value) else {
+        return ByteRange::Whole;
+    };
+    if len == 0 {
+        return ByteRange::Unsatisfiable;
+    }
+    let (start, end) = match (first, last) {
+        (Some(first), last) => (first, last.map_or(len - 1, |last| last.min(len - 1))),
+        (None, suffix) => (len.saturating_sub(suffix.unwrap_or_default()), len - 1),
+    };
+    if start <= end {
+        ByteRange::Partial(start, end)
+    } else {
+        ByteRange::Unsatisfiable
+    }
+}
+
+// First and last byte of a single `bytes=first-last` range, either of which may be left out
+fn parse_range_spec(value: &HeaderValue) -> Option<(Option<u64>, Option<u64>)> {
+    let spec = value.to_str().ok()?.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let parse = |position: &str| match position.trim() {
+        "" => Some(None),
+        position => position.parse().ok().map(Some),
+    };
+    match (parse(first)?, parse(last)?) {
+        (None, None) => None,
+        (Some(first), Some(last)) if last < first => None,
+        positions => Some(positions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_stays_inside_the_directory() {
+        assert_eq!(
+            resolve("public", "/css/site.css"),
+            Some(PathBuf::from("public/css/site.css"))
+        );
+        assert_eq!(
+            resolve("public", "/my%20file.txt"),
+            Some(PathBuf::from("public/my file.txt"))
+        );
+        assert_eq!(resolve("public", "/./a"), Some(PathBuf::from("public/a")));
+        assert_eq!(resolve("public", "/../secret"), None);
+        assert_eq!(resolve("public", "/a/../../secret"), None);
+        assert_eq!(resolve("public", "/%2e%2e/secret"), None);
+        assert_eq!(resolve("public", "/%2E%2E/secret"), None);
+        assert_eq!(resolve("public", "/..%2fsecret"), None);
+        assert_eq!(resolve("public", "/..%5csecret"), None);
+        assert_eq!(resolve("public", "/file%00.txt"), None);
+    }
+
+    #[test]
+    fn percent_decode_rejects_invalid_escapes() {
+        assert_eq!(percent_decode("a%41b").as_deref(), Some("aAb"));
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%ff"), None);
+        assert_eq!(resolve("public", "/%g0"), None);
+    }
+
+    #[test]
+    fn parse_range_reads_single_ranges() {
+        let range = |value: &'static str| parse_range(&HeaderValue::from_static(value), 100);
+        assert_eq!(range("bytes=0-9"), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=90-"), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=-10"), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=-500"), ByteRange::Partial(0, 99));
+        assert_eq!(range("bytes=50-500"), ByteRange::Partial(50, 99));
+        assert_eq!(range("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(
+            parse_range(&HeaderValue::from_static("bytes=0-"), 0),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn parse_range_ignores_multiple_and_malformed_ranges() {
+        let range = |value: &'static str| parse_range(&HeaderValue::from_static(value), 100);
+        assert_eq!(range("bytes=0-9,20-29"), ByteRange::Whole);
+        assert_eq!(range("bytes=9-0"), ByteRange::Whole);
+        assert_eq!(range("bytes=-"), ByteRange::Whole);
+        assert_eq!(range("bytes=a-b"), ByteRange::Whole);
+        assert_eq!(range("items=0-9"), ByteRange::Whole);
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use alloy_primitives::{hex, Address};
+use forge::executors::Executor;
+use http_body_util::BodyExt;
+use hyper::{header::CONTENT_LENGTH, Response};
+use tokio::sync::Mutex;
+
+use crate::forgery::{
+    body::{ChannelBody, ResponseBody},
+    events::EventBus,
+    project::{self, Caches},
+    rate_limit::Throttle,
+    types::nextCall,
+};
+
+/// Header through which a handler starts a stream, holding the hex encoded first cursor
+pub const CURSOR_HEADER: &str = "x-forgery-cursor";
+
+/// Removes the stream cursor from a response, if the handler set one.
+pub fn take_cursor(response: &mut Response<ResponseBody>) -> Option<Vec<u8>> {
+    let value = response.headers_mut().remove(CURSOR_HEADER)?;
+    match hex::decode(value.as_bytes()) {
+        Ok(cursor) => Some(cursor),
+        Err(err) => {
+            println!("Invalid stream cursor: {}", err);
+            None
+        }
+    }
+}
+
+/// Streams the body of a response chunk by chunk. The body returned by `serve()` is sent
+/// first, then `next(cursor)` is called until it returns an empty cursor. The executor is
+/// released between chunks, so other requests are served meanwhile. Every chunk is admitted
+/// and charged under the clients of the request, a client over its limit is sent chunks more
+/// slowly.
+pub fn stream(
+    response: Response<ResponseBody>,
+    cursor: Vec<u8>,
+    executor_mutex: Arc<Mutex<Executor>>,
+    address: Address,
+    events: EventBus,
+    caches: Caches,
+    throttle: Throttle,
+) -> Response<ResponseBody> {
+    let (mut parts, first) = response.into_parts();
+    // The length isn't known up front, hyper falls back to chunked encoding
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let (sender, body) = ChannelBody::new(4);
+    tokio::task::spawn(async move {
+        let first = match first.collect().await {
+            Ok(first) => first.to_bytes(),
+            Err(never) => match never {},
+        };
+        if sender.send(first).await.is_err() {
+            return;
+        }
+
+        let mut cursor = cursor;
+        while !cursor.is_empty() {
+            throttle.admit().await;
+            let call = nextCall { cursor };
+            let mut executor = executor_mutex.lock().await;
+            let chunk = match project::call_hook(&mut executor, &caches, address, &call) {
+                Ok(output) => {
+                    throttle.charge(output.gas_used);
+                    events.publish(address, &output.logs);
+                    output.returns._0
+                }
+                // Headers are already out, all that's left is ending the body early
+                Err(err) => {
+                    println!("{}", err);
+                    break;
+                }
+            };
+            drop(executor);
+
+            if sender.send(chunk.data.into()).await.is_err() {
+                break;
+            }
+            cursor = chunk.cursor;
+        }
+    });
+
+    Response::from_parts(parts, body)
+}
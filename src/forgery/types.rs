@@ -8,6 +8,8 @@ use hyper::{
     HeaderMap, Request, Response, StatusCode,
 };
 
+use crate::forgery::body::{self, ResponseBody};
+
 sol! {
 struct SolHttpHeader {
     string key;
@@ -27,8 +29,39 @@ struct SolHttpResponse {
     bytes body;
 }
 
+struct SolConfigEntry {
+    string key;
+    string value;
+}
+
+struct SolPrefetch {
+    address account;
+    bytes32[] slots;
+}
+
+struct SolWsMessage {
+    uint256 connection;
+    bytes data;
+}
+
+struct SolHttpChunk {
+    bytes data;
+    bytes cursor;
+}
+
+error ForgeryFetch(SolHttpRequest request);
+
+function configure (SolConfigEntry[] calldata) external;
 function start () external;
+function onBlock () external;
+function onConnect (uint256 connection, SolHttpRequest calldata request) external returns (SolWsMessage[] memory);
+function onMessage (uint256 connection, address sender, bytes calldata message) external returns (SolWsMessage[] memory);
+function onClose (uint256 connection) external;
+function prefetch () external view returns (SolPrefetch[] memory);
+function beforeServe (SolHttpRequest calldata request) external returns (bool respond, SolHttpResponse memory response);
 function serve (SolHttpRequest calldata) external returns (SolHttpResponse memory);
+function afterServe (SolHttpRequest calldata request, SolHttpResponse calldata response) external returns (SolHttpResponse memory);
+function next (bytes calldata cursor) external returns (SolHttpChunk memory);
 }
 
 struct ForgeryHeaderMap {
@@ -43,18 +76,19 @@ impl From<&HeaderMap> for ForgeryHeaderMap {
     }
 }
 
-impl From<Vec<SolHttpHeader>> for ForgeryHeaderMap {
-    fn from(headers: Vec<SolHttpHeader>) -> Self {
+// Headers come from handlers, which may return names or values HTTP doesn't allow
+impl TryFrom<Vec<SolHttpHeader>> for ForgeryHeaderMap {
+    type Error = hyper::http::Error;
+
+    fn try_from(headers: Vec<SolHttpHeader>) -> Result<Self, Self::Error> {
         let mut map = HeaderMap::new();
-        for header in headers.clone() {
+        for header in headers {
             map.insert(
-                HeaderName::from_str(&header.key).unwrap(),
-                HeaderValue::from_str(&header.value).unwrap(),
+                HeaderName::from_str(&header.key)?,
+                HeaderValue::from_str(&header.value)?,
             );
         }
-        ForgeryHeaderMap {
-            headers: map.clone(),
-        }
+        Ok(ForgeryHeaderMap { headers: map })
     }
 }
 
@@ -64,7 +98,7 @@ impl From<ForgeryHeaderMap> for Vec<SolHttpHeader> {
             .iter()
             .map(|(key, value)| SolHttpHeader {
                 key: key.to_string(),
-                value: value.to_str().unwrap().to_string(),
+                value: String::from_utf8_lossy(value.as_bytes()).to_string(),
             })
             .collect()
     }
@@ -84,23 +118,51 @@ impl SolHttpRequest {
             body,
         })
     }
+
+    pub fn into_outgoing(self) -> Result<Request<Full<HyperBytes>>, hyper::http::Error> {
+        let mut builder = Request::builder()
+            .method(self.method.as_str())
+            .uri(self.uri.as_str());
+
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(ForgeryHeaderMap::try_from(self.headers)?.headers);
+        }
+
+        builder.body(Full::new(HyperBytes::from(self.body)))
+    }
+}
+
+impl SolHttpResponse {
+    pub async fn from_incoming<B>(res: Response<B>) -> Result<Self, B::Error>
+    where
+        B: hyper::body::Body,
+    {
+        let status = res.status().as_u16();
+        let headers = ForgeryHeaderMap::from(res.headers()).into();
+        let bytes = res.collect().await?.to_bytes();
+        let body = bytes.iter().cloned().collect::<Vec<u8>>();
+        Ok(SolHttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
 }
 
-impl From<SolHttpResponse> for Response<Full<HyperBytes>> {
-    fn from(val: SolHttpResponse) -> Self {
+impl TryFrom<SolHttpResponse> for Response<ResponseBody> {
+    type Error = hyper::http::Error;
+
+    fn try_from(val: SolHttpResponse) -> Result<Self, Self::Error> {
         let mut builder =
             Response::builder().status(&StatusCode::from_u16(val.status).unwrap_or_else(|err| {
                 println!("Malformed response from index contract: {}", err);
                 StatusCode::INTERNAL_SERVER_ERROR
             }));
 
-        builder
-            .headers_mut()
-            .expect("Failed to generate response headers")
-            .extend(ForgeryHeaderMap::from(val.headers).headers);
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(ForgeryHeaderMap::try_from(val.headers)?.headers);
+        }
 
-        builder
-            .body(Full::new(HyperBytes::from(val.body)))
-            .expect("Failed to decode SolHttpResponse into hyper::Response")
+        builder.body(body::full(val.body))
     }
 }
@@ -0,0 +1,72 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use eyre::{ErrReport, Result};
+use forge::executors::Executor;
+use foundry_config::Config;
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::forgery::{
+    config::ForgeryConfig,
+    routes::{self, RouteTable},
+    types::SolConfigEntry,
+};
+
+/// Watches the project sources and redeploys the index contracts whenever they change.
+/// If the new version fails to build or deploy, the previous one keeps serving.
+pub async fn watch(
+    forgery_config: ForgeryConfig,
+    settings: Vec<SolConfigEntry>,
+    executor_mutex: Arc<Mutex<Executor>>,
+    route_table: Arc<RwLock<RouteTable>>,
+) -> Result<(), ErrReport> {
+    let config = Config::load();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher.watch(&config.src, RecursiveMode::Recursive)?;
+
+    println!("Watching {} for changes", config.src.display());
+
+    while rx.recv().await.is_some() {
+        // Editors usually emit several events for a single save, let them settle
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while rx.try_recv().is_ok() {}
+
+        println!("Change detected, rebuilding...");
+        match reload(&forgery_config, &settings, &executor_mutex, &route_table).await {
+            Ok(()) => println!("... done!"),
+            Err(err) => println!("Reload failed, keeping previous version:\n{}", err),
+        }
+    }
+
+    Ok(())
+}
+
+async fn reload(
+    forgery_config: &ForgeryConfig,
+    settings: &[SolConfigEntry],
+    executor_mutex: &Arc<Mutex<Executor>>,
+    route_table: &Arc<RwLock<RouteTable>>,
+) -> Result<(), ErrReport> {
+    let route_configs = forgery_config.routes();
+    let nonces = routes::sender_nonces(&*executor_mutex.lock().await, &route_configs)?;
+    let builds =
+        tokio::task::spawn_blocking(move || routes::build(&route_configs, nonces)).await??;
+
+    // Hold the executor while deploying so no request sees a half-deployed route table.
+    // Snapshots are keyed by address, which redeployed contracts don't share, so nothing
+    // is restored.
+    let mut executor = executor_mutex.lock().await;
+    let new_table = routes::deploy(&mut executor, builds, None, settings)?;
+    *route_table.write().unwrap() = new_table;
+
+    Ok(())
+}
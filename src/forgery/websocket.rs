@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolCall;
+use eyre::{ErrReport, Result};
+use forge::executors::Executor;
+use futures_util::{SinkExt, StreamExt};
+use hyper::{
+    body::Incoming,
+    header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
+    Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+
+use crate::forgery::{
+    auth::{self, Auth},
+    body::{self, ResponseBody},
+    events::EventBus,
+    project::{self, Caches},
+    rate_limit::{Clients, RateLimiter, Throttle},
+    routes::Route,
+    types::{onCloseCall, onConnectCall, onMessageCall, SolHttpRequest, SolWsMessage},
+};
+
+/// Whether a request asks to upgrade the connection to a WebSocket.
+pub fn is_upgrade<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Open WebSocket connections, each mapped to the `onConnect`, `onMessage` and `onClose`
+/// hooks of the index contract that accepted it.
+pub struct WebSockets {
+    executor: Arc<Mutex<Executor>>,
+    events: EventBus,
+    /// Admits every message and is charged the gas of every hook, under the clients the
+    /// handshake was admitted under
+    rate_limiter: Arc<RateLimiter>,
+    /// Authenticates the handshake, whose sender is passed to every hook
+    auth: Option<Arc<Auth>>,
+    caches: Caches,
+    next_id: AtomicU64,
+    /// Open connections, along with the index contract serving each of them
+    connections: StdMutex<HashMap<u64, Connection>>,
+}
+
+struct Connection {
+    route: Address,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl WebSockets {
+    pub fn new(
+        executor: Arc<Mutex<Executor>>,
+        events: EventBus,
+        rate_limiter: Arc<RateLimiter>,
+        auth: Option<Arc<Auth>>,
+        caches: Caches,
+    ) -> Self {
+        WebSockets {
+            executor,
+            events,
+            rate_limiter,
+            auth,
+            caches,
+            next_id: AtomicU64::new(1),
+            connections: Default::default(),
+        }
+    }
+
+    /// Authenticates the handshake, completes it and serves the connection once hyper hands
+    /// it over.
+    pub async fn upgrade(
+        self: &Arc<Self>,
+        mut req: Request<Incoming>,
+        route: Route,
+        clients: Clients,
+    ) -> Response<ResponseBody> {
+        if !route.implements("onMessage") {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Index contract doesn't accept WebSockets",
+            );
+        }
+        let Some(key) = req.headers().get(SEC_WEBSOCKET_KEY) else {
+            return error_response(StatusCode::BAD_REQUEST, "Missing Sec-WebSocket-Key header");
+        };
+        let accept = derive_accept_key(key.as_bytes());
+
+        let upgrade = hyper::upgrade::on(&mut req);
+        let mut request = match SolHttpRequest::from_incoming(req).await {
+            Ok(request) => request,
+            Err(err) => {
+                println!("{}", err);
+                return error_response(StatusCode::BAD_REQUEST, "Request parsing failed");
+            }
+        };
+        let sender = match &self.auth {
+            Some(auth) => match auth.authenticate(&request) {
+                Ok(sender) => sender,
+                Err(err) => return auth::unauthorized(err),
+            },
+            None => Address::ZERO,
+        };
+        auth::set_sender(&mut request, sender);
+
+        let throttle = Throttle::new(self.rate_limiter.clone(), clients);
+        let websockets = self.clone();
+        tokio::task::spawn(async move {
+            match upgrade.await {
+                Ok(upgraded) => {
+                    let stream = WebSocketStream::from_raw_socket(
+                        TokioIo::new(upgraded),
+                        Role::Server,
+                        None,
+                    )
+                    .await;
+                    websockets
+                        .serve(stream, route, request, sender, throttle)
+                        .await;
+                }
+                Err(err) => println!("Error upgrading connection: {}", err),
+            }
+        });
+
+        Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_ACCEPT, accept)
+            .body(body::full(""))
+            .unwrap()
+    }
+
+    async fn serve<S>(
+        &self,
+        stream: WebSocketStream<S>,
+        route: Route,
+        request: SolHttpRequest,
+        sender_address: Address,
+        throttle: Throttle,
+    ) where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, mut outgoing) = mpsc::unbounded_channel();
+        self.connections.lock().unwrap().insert(
+            id,
+            Connection {
+                route: route.address,
+                sender,
+            },
+        );
+        let (mut sink, mut incoming) = stream.split();
+
+        let connected = if route.implements("onConnect") {
+            let call = onConnectCall {
+                connection: U256::from(id),
+                request,
+            };
+            self.call(&route, &call, &throttle).await.map(|ret| ret._0)
+        } else {
+            Ok(Vec::new())
+        };
+        // A reverting onConnect() rejects the connection
+        match connected {
+            Ok(messages) => self.dispatch(route.address, messages),
+            Err(err) => {
+                println!("{}", err);
+                let _ = sink.close().await;
+                self.connections.lock().unwrap().remove(&id);
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                message = incoming.next() => {
+                    let data = match message {
+                        Some(Ok(Message::Text(text))) => text.into_bytes(),
+                        Some(Ok(Message::Binary(data))) => data,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        // Pings are answered by tungstenite
+                        Some(Ok(_)) => continue,
+                    };
+                    // Clients over their limit wait, and their messages queue up meanwhile
+                    throttle.admit().await;
+                    let call = onMessageCall {
+                        connection: U256::from(id),
+                        sender: sender_address,
+                        message: data,
+                    };
+                    match self.call(&route, &call, &throttle).await {
+                        Ok(ret) => self.dispatch(route.address, ret._0),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                Some(message) = outgoing.recv() => {
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.connections.lock().unwrap().remove(&id);
+        if route.implements("onClose") {
+            let call = onCloseCall {
+                connection: U256::from(id),
+            };
+            if let Err(err) = self.call(&route, &call, &throttle).await {
+                println!("{}", err);
+            }
+        }
+    }
+
+    async fn call<C: SolCall>(
+        &self,
+        route: &Route,
+        call: &C,
+        throttle: &Throttle,
+    ) -> Result<C::Return, ErrReport> {
+        let mut executor = self.executor.lock().await;
+        let output = project::call_hook(&mut executor, &self.caches, route.address, call)?;
+        drop(executor);
+        throttle.charge(output.gas_used);
+        self.events.publish(route.address, &output.logs);
+        Ok(output.returns)
+    }
+
+    /// Sends the messages returned by a hook of an index contract. Connection `0` broadcasts
+    /// to every connection. Only the contract's own connections can be reached.
+    fn dispatch(&self, route: Address, messages: Vec<SolWsMessage>) {
+        let connections = self.connections.lock().unwrap();
+        for message in messages {
+            let frame = match String::from_utf8(message.data) {
+                Ok(text) => Message::Text(text),
+                Err(err) => Message::Binary(err.into_bytes()),
+            };
+            if message.connection == U256::ZERO {
+                for connection in connections.values().filter(|c| c.route == route) {
+                    let _ = connection.sender.send(frame.clone());
+                }
+            } else if let Some(connection) = u64::try_from(message.connection)
+                .ok()
+                .and_then(|id| connections.get(&id))
+                .filter(|connection| connection.route == route)
+            {
+                let _ = connection.sender.send(frame);
+            }
+        }
+    }
+}
+
+fn error_response(status: StatusCode, message: &'static str) -> Response<ResponseBody> {
+    Response::builder()
+        .status(status)
+        .body(body::full(message))
+        .unwrap()
+}
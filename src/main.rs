@@ -1,98 +1,484 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
-use std::{convert::Infallible, thread};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
 
-use alloy_primitives::{Address, U256};
-use alloy_providers::provider::{Provider, TempProvider};
+use alloy_primitives::{Address, B256, U256};
 use alloy_sol_types::{SolCall, SolValue};
 use dotenv::dotenv;
-use eyre::Result;
-use forge::revm::precompile::{Precompiles, SpecId as PrecompileSpec};
-use forge::revm::primitives::SpecId;
-use forge::revm::JournaledState;
+use eyre::{ErrReport, Result};
 use forge::{
     decode::decode_console_logs,
-    executors::Executor,
+    executors::{Executor, RawCallResult},
     opts::Env,
     opts::EvmOpts,
     revm::primitives::{db::DatabaseCommit, BlobExcessGasAndPrice, BlockEnv, Env as RevmEnv},
 };
-use foundry_evm_core::backend::DatabaseExt;
-use http_body_util::Full;
-use hyper::body::Bytes as HyperBytes;
+use foundry_cheatcodes::BroadcastableTransactions;
+use hyper::header::{HeaderValue, ACCEPT_ENCODING, CONNECTION, ORIGIN};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{body::Incoming, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, MutexGuard};
 
-use crate::forgery::project;
-use crate::forgery::types::{serveCall, SolHttpRequest, SolHttpResponse};
+use crate::forgery::access::Access;
+use crate::forgery::auth::{self, Auth, AUTH_PATH};
+use crate::forgery::body::{self, ResponseBody};
+use crate::forgery::broadcast::Broadcaster;
+use crate::forgery::cors::Cors;
+use crate::forgery::events::{self, EventBus};
+use crate::forgery::fetch::Fetcher;
+use crate::forgery::fork::ForkFollower;
+use crate::forgery::genesis;
+use crate::forgery::health::Health;
+use crate::forgery::persistence::{self, Snapshot};
+use crate::forgery::rate_limit::{Clients, RateLimiter, Throttle};
+use crate::forgery::response_cache::{CacheKey, ResponseCache};
+use crate::forgery::routes::{self, RouteTable};
+use crate::forgery::static_files::StaticFiles;
+use crate::forgery::stream;
+use crate::forgery::types::{
+    afterServeCall, beforeServeCall, serveCall, SolConfigEntry, SolHttpRequest, SolHttpResponse,
+};
+use crate::forgery::websocket::{self, WebSockets};
+use crate::forgery::{
+    compression,
+    config::{CompressionConfig, ForgeryConfig, ForkBlock},
+    project::{self, Caches},
+    watch,
+};
 pub mod cmd;
 pub mod forgery;
 
-async fn forgery(
-    executor_arc: Arc<Mutex<Executor>>,
-    index_addr: Address,
+/// Response header reporting the gas used by each stage of a request
+const GAS_HEADER: &str = "x-forgery-gas";
+/// Hashes of the transactions a request broadcast
+const TRANSACTIONS_HEADER: &str = "x-forgery-transactions";
+
+#[derive(Clone)]
+struct ServerState {
+    executor: Arc<Mutex<Executor>>,
+    route_table: Arc<RwLock<RouteTable>>,
+    fetcher: Arc<Fetcher>,
+    caches: Caches,
+    broadcaster: Option<Arc<Broadcaster>>,
+    events: EventBus,
+    websockets: Arc<WebSockets>,
+    compression: Arc<CompressionConfig>,
+    cors: Option<Arc<Cors>>,
+    static_files: Arc<StaticFiles>,
+    rate_limiter: Arc<RateLimiter>,
+    auth: Option<Arc<Auth>>,
+    access: Arc<Access>,
+    health: Arc<Health>,
+}
+
+/// Handles CORS, access rules and rate limits before requests reach the index contracts.
+/// Health checks are answered first, they are neither limited nor authorized.
+async fn serve(
+    state: ServerState,
+    peer: IpAddr,
     req: Request<Incoming>,
-) -> Result<Response<Full<HyperBytes>>, Infallible> {
-    let mut executor = executor_arc.lock().await;
-    let fn_call = match SolHttpRequest::from_incoming(req).await {
-        Ok(req_struct) => serveCall { _0: req_struct },
+) -> Result<Response<ResponseBody>, Infallible> {
+    if let Some(response) = state.health.serve(&req) {
+        return Ok(response);
+    }
+    let Some(cors) = state.cors.clone() else {
+        return limit(state, peer, req).await;
+    };
+    if let Some(response) = cors.preflight(&req) {
+        return Ok(response);
+    }
+    let origin = req.headers().get(ORIGIN).cloned();
+    let mut response = limit(state, peer, req).await?;
+    cors.apply(origin.as_ref(), &mut response);
+    Ok(response)
+}
+
+/// Rejects requests without the API key or JWT their path requires, and clients over their
+/// rate limit. Verified claims are forwarded to handlers as request headers, and identify
+/// clients to the rate limiter, so failed attempts count against the IP they came from.
+/// Admitted requests carry their clients, which the gas of the call is charged to.
+async fn limit(
+    state: ServerState,
+    peer: IpAddr,
+    mut req: Request<Incoming>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let authorized = state.access.authorize(&mut req);
+    let clients = match state.rate_limiter.admit(peer, &req) {
+        Ok(clients) => clients,
+        Err(response) => return Ok(response),
+    };
+    if let Err(err) = authorized {
+        return Ok(auth::unauthorized(err));
+    }
+    req.extensions_mut().insert(clients);
+    forgery(state, req).await
+}
+
+async fn forgery(
+    state: ServerState,
+    mut req: Request<Incoming>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    if let Some(auth) = &state.auth {
+        if routes::matches_prefix(AUTH_PATH, req.uri().path()) {
+            return Ok(auth.serve(req).await);
+        }
+    }
+    if let Some(response) = state.static_files.serve(&req).await {
+        return Ok(response);
+    }
+
+    let route = state.route_table.read().unwrap().resolve(&req).cloned();
+    let route = match route {
+        Some(route) => route,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(body::full("No index contract serves this path"))
+                .unwrap())
+        }
+    };
+    if events::is_subscribe(&req, &route.config.prefix) {
+        return Ok(state.events.subscribe(route.address, &req));
+    }
+    let clients = req.extensions_mut().remove::<Clients>().unwrap_or_default();
+    if websocket::is_upgrade(&req) {
+        return Ok(state.websockets.upgrade(req, route, clients).await);
+    }
+
+    let accept_encoding = req.headers().get(ACCEPT_ENCODING).cloned();
+    let mut cache_key = state
+        .caches
+        .responses
+        .as_ref()
+        .and_then(|response_cache| response_cache.key(&req, route.address));
+    // Credentials are only checked once the request is parsed, and responses to them are
+    // the sender's own
+    if state.auth.is_some() && auth::has_credentials(&req) {
+        cache_key = None;
+    }
+    if let (Some(response_cache), Some(key)) = (&state.caches.responses, &cache_key) {
+        if let Some(response) = response_cache.get(key) {
+            return Ok(compression::compress(
+                &state.compression,
+                accept_encoding.as_ref(),
+                response,
+            )
+            .await);
+        }
+    }
+    let mut request = match SolHttpRequest::from_incoming(req).await {
+        Ok(req_struct) => req_struct,
         Err(err) => {
             println!("{}", err);
             return Ok(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::new(HyperBytes::from("Request parsing failed")))
+                .body(body::full("Request parsing failed"))
                 .unwrap());
         }
     };
-    let calldata = fn_call.abi_encode();
-    let call = executor.call_raw(Address::ZERO, index_addr, calldata.into(), U256::ZERO);
-    match call {
-        Ok(res) => {
-            if let Some(changes) = &res.state_changeset {
-                executor.backend.commit(changes.clone());
+    if let Err(err) = compression::decompress_request(&state.compression, &mut request) {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(body::full(err.to_string()))
+            .unwrap());
+    }
+    let sender = match &state.auth {
+        Some(auth) => match auth.authenticate(&request) {
+            Ok(sender) => sender,
+            Err(err) => return Ok(auth::unauthorized(err)),
+        },
+        None => Address::ZERO,
+    };
+    auth::set_sender(&mut request, sender);
+    // The body is read, decompressed and authenticated before taking the executor, so slow
+    // clients and large bodies don't hold up other requests
+    let mut executor = state.executor.lock().await;
+    let mut gas = Vec::new();
+    // Shared by every stage, so the fetch limit holds for the request as a whole
+    let mut fetches = 0;
+    let mut transactions = BroadcastableTransactions::default();
+    let mut response = None;
+    if route.implements("beforeServe") {
+        let calldata = beforeServeCall {
+            request: request.clone(),
+        }
+        .abi_encode();
+        let (guard, res) = call_stage(
+            &state,
+            executor,
+            route.address,
+            calldata,
+            &clients,
+            &mut cache_key,
+            &mut fetches,
+        )
+        .await;
+        executor = guard;
+        let mut res = match res {
+            Ok(res) => res,
+            Err(response) => return Ok(response),
+        };
+        gas.push(("beforeServe", res.gas_used));
+        transactions.extend(res.transactions.take().unwrap_or_default());
+        match beforeServeCall::abi_decode_returns(&res.result, true) {
+            Ok(ret) if ret.respond => response = Some(ret.response),
+            Ok(_) => {}
+            Err(err) => return Ok(parsing_failed(err)),
+        }
+    }
+
+    let request_copy = route.implements("afterServe").then(|| request.clone());
+    let response = match response {
+        Some(response) => response,
+        None => {
+            let calldata = serveCall { _0: request }.abi_encode();
+            let (guard, res) = call_stage(
+                &state,
+                executor,
+                route.address,
+                calldata,
+                &clients,
+                &mut cache_key,
+                &mut fetches,
+            )
+            .await;
+            executor = guard;
+            let mut res = match res {
+                Ok(res) => res,
+                Err(response) => return Ok(response),
+            };
+            gas.push(("serve", res.gas_used));
+            transactions.extend(res.transactions.take().unwrap_or_default());
+            match SolHttpResponse::abi_decode(&res.result, true) {
+                Ok(response) => response,
+                Err(err) => return Ok(parsing_failed(err)),
             }
+        }
+    };
 
-            let console_logs = decode_console_logs(&res.logs);
-            if !console_logs.is_empty() {
-                for log in console_logs {
-                    println!("{}", log);
-                }
+    let response = match request_copy {
+        Some(request) => {
+            let calldata = afterServeCall { request, response }.abi_encode();
+            let (guard, res) = call_stage(
+                &state,
+                executor,
+                route.address,
+                calldata,
+                &clients,
+                &mut cache_key,
+                &mut fetches,
+            )
+            .await;
+            executor = guard;
+            let mut res = match res {
+                Ok(res) => res,
+                Err(response) => return Ok(response),
+            };
+            gas.push(("afterServe", res.gas_used));
+            transactions.extend(res.transactions.take().unwrap_or_default());
+            match afterServeCall::abi_decode_returns(&res.result, true) {
+                Ok(ret) => ret._0,
+                Err(err) => return Ok(parsing_failed(err)),
             }
+        }
+        None => response,
+    };
 
-            if !res.reverted {
-                match SolHttpResponse::abi_decode(&res.result, true) {
-                    Ok(value) => Ok(value.into()),
-                    Err(err) => {
-                        println!("Error parsing response from contract: {}", err);
-                        Ok(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Full::new(HyperBytes::from("Response parsing failed")))
-                            .unwrap())
-                    }
-                }
-            } else {
-                let reason = res.exit_reason;
-                Ok(Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Full::new(HyperBytes::from(format!(
-                        "Request reverted: {reason:#?}"
-                    ))))
-                    .unwrap())
+    let mut response: Response<ResponseBody> = match response.try_into() {
+        Ok(response) => response,
+        Err(err) => {
+            println!("Malformed response from index contract: {}", err);
+            return Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(body::full("Malformed response"))
+                .unwrap());
+        }
+    };
+    let gas = gas
+        .iter()
+        .map(|(stage, gas_used)| format!("{stage}={gas_used}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    response
+        .headers_mut()
+        .insert(GAS_HEADER, HeaderValue::from_str(&gas).unwrap());
+
+    let broadcaster = state
+        .broadcaster
+        .as_ref()
+        .filter(|_| !transactions.is_empty());
+    let cursor = stream::take_cursor(&mut response);
+    // Stored before releasing the executor, so no write lands between running the request
+    // and caching its response. Broadcasting and streamed responses are never cached.
+    if let (None, None, Some(response_cache), Some(key)) =
+        (broadcaster, &cursor, &state.caches.responses, cache_key)
+    {
+        // Gas is reported for the request that ran, cache hits don't use any
+        let gas = response.headers_mut().remove(GAS_HEADER);
+        response = response_cache.store(key, response).await;
+        if let Some(gas) = gas {
+            response.headers_mut().insert(GAS_HEADER, gas);
+        }
+    }
+    // Broadcasting and compressing don't touch the executor, other requests go ahead meanwhile
+    drop(executor);
+
+    if let Some(broadcaster) = broadcaster {
+        match broadcaster.send(transactions).await {
+            Ok(hashes) => {
+                response
+                    .headers_mut()
+                    .insert(TRANSACTIONS_HEADER, transaction_hashes(&hashes));
+            }
+            Err(err) => {
+                println!("{:#}", err.error);
+                // Transactions sent before the failure are on their way, clients need to know
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .header(TRANSACTIONS_HEADER, transaction_hashes(&err.hashes))
+                    .body(body::full("Broadcasting transactions failed"))
+                    .unwrap());
             }
         }
+    }
+    let response = match cursor {
+        Some(cursor) => stream::stream(
+            response,
+            cursor,
+            state.executor.clone(),
+            route.address,
+            state.events.clone(),
+            state.caches.clone(),
+            Throttle::new(state.rate_limiter.clone(), clients),
+        ),
+        None => compression::compress(&state.compression, accept_encoding.as_ref(), response).await,
+    };
+    Ok(response)
+}
+
+/// Runs one stage of a request on the index contract. The executor may be released while
+/// the stage fetches, the guard held at the end is handed back.
+async fn call_stage<'a>(
+    state: &'a ServerState,
+    executor: MutexGuard<'a, Executor>,
+    address: Address,
+    calldata: Vec<u8>,
+    clients: &Clients,
+    cache_key: &mut Option<CacheKey>,
+    fetches: &mut usize,
+) -> (
+    MutexGuard<'a, Executor>,
+    Result<RawCallResult, Response<ResponseBody>>,
+) {
+    let (mut executor, res) = state
+        .fetcher
+        .call(
+            &state.executor,
+            executor,
+            Address::ZERO,
+            address,
+            calldata.into(),
+            fetches,
+        )
+        .await;
+    let res = finish_stage(state, &mut executor, address, res, clients, cache_key);
+    (executor, res)
+}
+
+/// Commits the state changes of a stage, prints its console logs and publishes its events.
+/// Stages that revert or fail are answered with an error response.
+fn finish_stage(
+    state: &ServerState,
+    executor: &mut Executor,
+    address: Address,
+    res: Result<RawCallResult, ErrReport>,
+    clients: &Clients,
+    cache_key: &mut Option<CacheKey>,
+) -> Result<RawCallResult, Response<ResponseBody>> {
+    let res = match res {
+        Ok(res) => res,
         Err(err) => {
             println!("{}", err);
-            Ok(Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::new(HyperBytes::from("Forgery encountered an error")))
-                .unwrap())
+                .body(body::full("Forgery encountered an error"))
+                .unwrap());
+        }
+    };
+
+    state.rate_limiter.charge(clients, res.gas_used);
+    if let Some(changes) = &res.state_changeset {
+        // Writes may change the response to any request, including this one
+        if state.caches.commit(executor, changes) {
+            *cache_key = None;
+        }
+    }
+
+    let console_logs = decode_console_logs(&res.logs);
+    if !console_logs.is_empty() {
+        for log in console_logs {
+            println!("{}", log);
         }
     }
+
+    if res.reverted {
+        let reason = res.exit_reason;
+        return Err(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(body::full(format!("Request reverted: {reason:#?}")))
+            .unwrap());
+    }
+    state.events.publish(address, &res.logs);
+    Ok(res)
+}
+
+/// Answers health checks while the project builds and deploys, so probes don't hang on a
+/// listener nothing accepts from. Other requests are turned away until it's done.
+async fn serve_starting(listener: Arc<TcpListener>, health: Arc<Health>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let health = health.clone();
+        tokio::task::spawn(async move {
+            let service = service_fn(|req: Request<Incoming>| {
+                let mut response = health.serve(&req).unwrap_or_else(|| {
+                    Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body(body::full("Forgery is starting"))
+                        .unwrap()
+                });
+                // Clients reconnect to reach the server once it's up
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+                async move { Ok::<_, Infallible>(response) }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+fn transaction_hashes(hashes: &[B256]) -> HeaderValue {
+    let hashes = hashes
+        .iter()
+        .map(|hash| hash.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    HeaderValue::from_str(&hashes).unwrap()
+}
+
+fn parsing_failed(err: alloy_sol_types::Error) -> Response<ResponseBody> {
+    println!("Error parsing response from contract: {}", err);
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(body::full("Response parsing failed"))
+        .unwrap()
 }
 
 #[tokio::main]
@@ -104,10 +490,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         cmd::init(args);
         return Ok(());
     }
+    if args.len() > 1 && args[1] == "cache" {
+        let config = ForgeryConfig::load(&args)?;
+        cmd::cache(args, &config.cache)?;
+        return Ok(());
+    }
+    let dev_mode = args.len() > 1 && args[1] == "dev";
+    let config = ForgeryConfig::load(&args)?;
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    let listener = TcpListener::bind(addr).await?;
-    let rpc_url = std::env::var("FORGERY_RPC").expect("FORGERY_RPC must be set");
+    let listener = Arc::new(TcpListener::bind(addr).await?);
+    let route_table = Arc::new(RwLock::new(RouteTable::default()));
+    let health = Arc::new(Health::new(
+        config.health.clone(),
+        route_table.clone(),
+        matches!(config.fork.block, ForkBlock::Number(_)),
+    ));
+    let starting = tokio::task::spawn(serve_starting(listener.clone(), health.clone()));
+    let endpoints = if config.offline {
+        Vec::new()
+    } else {
+        config.fork.endpoints(std::env::var("FORGERY_RPC").ok())
+    };
+    if !config.offline && endpoints.is_empty() {
+        panic!("FORGERY_RPC must be set, or use --offline");
+    }
+    let named_fork_endpoints = if config.offline {
+        Default::default()
+    } else {
+        config.named_fork_endpoints()
+    };
+    let mut settings = config.public_env();
+
+    // Secrets are read before they are scrubbed from the environment
+    let broadcaster = match &config.broadcast {
+        Some(broadcast) => {
+            let rpc_url = broadcast
+                .rpc
+                .clone()
+                .or_else(|| endpoints.first().map(|endpoint| endpoint.url.clone()))
+                .ok_or_else(|| eyre::eyre!("Broadcasting requires an RPC endpoint"))?;
+            let broadcaster = Broadcaster::new(broadcast, &rpc_url).await?;
+            println!("Broadcasting transactions from {}", broadcaster.address());
+            settings.push(SolConfigEntry {
+                key: "broadcast.sender".to_string(),
+                value: broadcaster.address().to_string(),
+            });
+            Some(Arc::new(broadcaster))
+        }
+        None => None,
+    };
+    let access = Arc::new(Access::new(config.access.clone())?);
+    config.scrub_env();
 
     let env = Env {
         gas_limit: u64::MAX,
@@ -126,7 +560,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     let opts = EvmOpts {
         env: env.clone(),
-        fork_url: Some(rpc_url.clone()),
+        fork_url: None,
         fork_block_number: None,
         fork_retries: Some(5),
         fork_retry_backoff: None,
@@ -134,13 +568,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         no_rpc_rate_limit: true,
         no_storage_caching: false,
         initial_balance: U256::from(0),
-        sender: Address::ZERO,
+        // Lets `vm.startBroadcast()` without arguments broadcast from the signing address
+        sender: broadcaster
+            .as_ref()
+            .map_or(Address::ZERO, |broadcaster| broadcaster.address()),
         ffi: false,
         verbosity: 1u8,
         memory_limit: u64::MAX,
     };
 
-    let mut revm_env = RevmEnv {
+    let revm_env = RevmEnv {
         block: BlockEnv {
             basefee: U256::from(env.block_base_fee_per_gas),
             coinbase: env.block_coinbase,
@@ -158,60 +595,160 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         tx: Default::default(),
     };
 
-    let mut executor = project::executor(opts.clone(), revm_env.clone())
+    let mut follower = if endpoints.is_empty() {
+        None
+    } else {
+        Some(
+            ForkFollower::connect(
+                config.fork.clone(),
+                endpoints,
+                opts.clone(),
+                revm_env.clone(),
+            )
+            .await?,
+        )
+    };
+    if let Some(follower) = &follower {
+        health.set_fork(follower.status());
+    }
+    let base_opts = opts.clone();
+    let opts = follower
+        .as_ref()
+        .map_or(opts, |follower| follower.evm_opts());
+
+    let mut executor = project::executor(opts, revm_env.clone())
         .await
         .expect("Failed to create EVM executor");
-    let build_result = project::build().expect("Project build failed");
-    let address = project::deploy(&mut executor, build_result).expect("Failed to deploy project");
+
+    let cache = match follower.as_mut() {
+        Some(follower) if config.cache.enabled => {
+            let cache = follower.open_cache(config.cache.clone()).await?;
+            cache.lock().unwrap().warm(&mut executor)?;
+            Some(cache)
+        }
+        _ => None,
+    };
+
+    // Named forks are created up front, handlers find their ids in configure()
+    let mut named_followers = Vec::new();
+    for (name, endpoints) in named_fork_endpoints {
+        let mut follower = ForkFollower::connect(
+            config.forks[&name].clone(),
+            endpoints,
+            base_opts.clone(),
+            revm_env.clone(),
+        )
+        .await?;
+        let fork_id = follower.create_fork(&mut executor)?;
+        println!("Created fork {} with id {}", name, fork_id);
+        settings.push(SolConfigEntry {
+            key: format!("fork.{name}"),
+            value: fork_id.to_string(),
+        });
+        named_followers.push(follower);
+    }
+
+    if let Some(alloc) = &config.alloc {
+        genesis::apply_alloc(&mut executor, alloc)?;
+    }
+    let route_configs = config.routes();
+    let nonces = routes::sender_nonces(&executor, &route_configs)?;
+    let builds = routes::build(&route_configs, nonces).expect("Project build failed");
+    let snapshot = match &config.persistence {
+        Some(persistence) => Some(Snapshot::load(&persistence.path)?),
+        None => None,
+    };
+    *route_table.write().unwrap() =
+        routes::deploy(&mut executor, builds, snapshot.as_ref(), &settings)
+            .expect("Failed to deploy project");
+    if let (Some(persistence), Some(snapshot)) = (&config.persistence, &snapshot) {
+        for address in persistence.accounts.iter() {
+            snapshot.restore(&mut executor, *address)?;
+        }
+    }
 
     println!("... done!");
     println!("Listening on port: {}", 3000);
 
     let executor_mutex = Arc::new(Mutex::new(executor));
-    let executor_mutex_clone = executor_mutex.clone();
-    tokio::task::spawn(async move {
-        let executor_mutex = executor_mutex_clone.clone();
-        let opts = opts.clone();
-        loop {
-            thread::sleep(Duration::from_secs(1));
-            let mut executor = executor_mutex.lock().await;
-            let fork_id = executor.backend.active_fork_id();
-            let mut journaled_state = JournaledState::new(
-                SpecId::CANCUN,
-                Precompiles::new(PrecompileSpec::CANCUN)
-                    .addresses()
-                    .into_iter()
-                    .copied()
-                    .collect(),
-            );
-            let fork_url = opts.clone().fork_url.clone().unwrap();
-            match Provider::try_from(&fork_url) {
-                Ok(provider) => match provider.get_block_number().await {
-                    Ok(block_number) => {
-                        let _ = executor.backend.roll_fork(
-                            fork_id,
-                            U256::from(block_number),
-                            &mut revm_env,
-                            &mut journaled_state,
-                        );
-                    }
-                    Err(e) => println!("Error getting latest block: {}", e),
-                },
-                Err(e) => println!("Error getting provider: {}", e),
+
+    if dev_mode {
+        let executor_mutex = executor_mutex.clone();
+        let route_table = route_table.clone();
+        let config = config.clone();
+        let settings = settings.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = watch::watch(config, settings, executor_mutex, route_table).await {
+                println!("Error watching project sources: {}", err);
             }
-        }
-    });
+        });
+    }
 
-    loop {
+    if let Some(persistence) = config.persistence.clone() {
         let executor_mutex = executor_mutex.clone();
-        let (stream, _) = listener.accept().await?;
+        let route_table = route_table.clone();
+        tokio::task::spawn(persistence::run(persistence, executor_mutex, route_table));
+    }
+
+    let events = EventBus::default();
+    let fork_status = follower.as_ref().map(|follower| follower.status());
+    let response_cache = config
+        .response_cache
+        .clone()
+        .map(|response_cache| Arc::new(ResponseCache::new(response_cache)));
+    let follower =
+        follower.map(|follower| follower.with_routes(route_table.clone(), events.clone()));
+    // Keep the fork at the configured block, there is nothing to follow offline
+    for follower in follower.into_iter().chain(named_followers) {
+        // Any fork moving may change what handlers read
+        let follower = match &response_cache {
+            Some(response_cache) => follower.with_response_cache(response_cache.clone()),
+            None => follower,
+        };
+        tokio::task::spawn(follower.follow(executor_mutex.clone()));
+    }
+    let caches = Caches {
+        fork: cache,
+        responses: response_cache,
+    };
+
+    let rate_limiter = Arc::new(RateLimiter::new(
+        config.rate_limits.clone(),
+        config.trusted_proxies.clone(),
+    ));
+    let auth = config.auth.clone().map(|auth| Arc::new(Auth::new(auth)));
+    let state = ServerState {
+        executor: executor_mutex.clone(),
+        route_table,
+        fetcher: Arc::new(Fetcher::new(config.fetch.clone()).with_fork(fork_status)),
+        caches: caches.clone(),
+        broadcaster,
+        websockets: Arc::new(WebSockets::new(
+            executor_mutex,
+            events.clone(),
+            rate_limiter.clone(),
+            auth.clone(),
+            caches,
+        )),
+        events,
+        compression: Arc::new(config.compression.clone()),
+        cors: config.cors.clone().map(|cors| Arc::new(Cors::new(cors))),
+        static_files: Arc::new(StaticFiles::new(config.static_files.clone())),
+        rate_limiter,
+        auth,
+        access,
+        health,
+    };
+
+    starting.abort();
+    loop {
+        let state = state.clone();
+        let (stream, peer) = listener.accept().await?;
         let io = TokioIo::new(stream);
         tokio::task::spawn(async move {
             if let Err(err) = http1::Builder::new()
-                .serve_connection(
-                    io,
-                    service_fn(|req| forgery(executor_mutex.clone(), address, req)),
-                )
+                .serve_connection(io, service_fn(|req| serve(state.clone(), peer.ip(), req)))
+                .with_upgrades()
                 .await
             {
                 println!("Error serving connection: {:?}", err);